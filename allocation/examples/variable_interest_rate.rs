@@ -0,0 +1,68 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Evaluate the Sturdy rate model on a batch of inputs, for the differential
+//! test in `tests/VariableInterestRate.t.sol`.
+//!
+//! Takes the hex encoded `abi.encode(SturdyStrategyDataParams[] datas,
+//! uint256[] utilizations)` as its only argument and prints the hex encoded
+//! `abi.encode(uint64[] ratesPerSec, uint64[] fullUtilizationRates)`, where
+//! each rate is `get_new_rate(curTimestamp - lastTimestamp, utilization,
+//! data)`.
+
+use std::env;
+
+use alloy_primitives::hex;
+use alloy_sol_types::{sol_data, SolType};
+use anyhow::{anyhow, bail, Context, Result};
+use bonsai_starter_allocation::{abi::SturdyStrategyDataParams, get_new_rate, SturdyDataParams};
+
+type Cases = (
+    sol_data::Array<SturdyStrategyDataParams>,
+    sol_data::Array<sol_data::Uint<256>>,
+);
+type Rates = (
+    sol_data::Array<sol_data::Uint<64>>,
+    sol_data::Array<sol_data::Uint<64>>,
+);
+
+fn main() -> Result<()> {
+    let encoded = env::args()
+        .nth(1)
+        .context("Usage: variable_interest_rate <hex encoded cases>")?;
+    let (datas, utilizations) = Cases::abi_decode_params(&hex::decode(encoded)?, true)
+        .map_err(|err| anyhow!("Failed to decode cases: {err}"))?;
+    if datas.len() != utilizations.len() {
+        bail!("Expected one utilization per case");
+    }
+
+    let mut rates_per_sec = Vec::with_capacity(datas.len());
+    let mut full_utilization_rates = Vec::with_capacity(datas.len());
+    for (data, utilization) in datas.into_iter().zip(utilizations) {
+        let sturdy_data = SturdyDataParams::from(data);
+        let delta_time = sturdy_data
+            .cur_timestamp
+            .checked_sub(sturdy_data.last_timestamp)
+            .context("curTimestamp is before lastTimestamp")?;
+        let (rate_per_sec, full_utilization_rate) =
+            get_new_rate(delta_time, utilization, sturdy_data)
+                .map_err(|err| anyhow!("Failed to compute rate: {err}"))?;
+        rates_per_sec.push(rate_per_sec);
+        full_utilization_rates.push(full_utilization_rate);
+    }
+
+    let output = Rates::abi_encode_params(&(rates_per_sec, full_utilization_rates));
+    println!("0x{}", hex::encode(output));
+    Ok(())
+}
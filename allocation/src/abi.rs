@@ -0,0 +1,648 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ABI of the `optimal_allocation` guest, mirroring `ZKOptimalAllocation`.
+//!
+//! The input is what `ZKOptimalAllocation.optimalAllocationInput` returns, a
+//! list of vaults, and the journal holds the argument of
+//! `ZKOptimalAllocation.onResult`, one result per vault in input order. Both
+//! are encoded as a parameter list, like `abi.encode(a, b, ...)`, rather than
+//! as a single struct.
+
+use alloc::{vec, vec::Vec};
+
+use alloy_primitives::{keccak256, U256};
+use alloy_sol_types::{sol, sol_data, SolType};
+
+use crate::{
+    error::AllocationError,
+    optimizer::{AllocationInput, AllocationResult, Objective, Position, StrategyParams},
+    rate_model::{self, RateModelKind},
+    vaults::{allocate_in_order, vault_failure},
+    Constraints, CostModel, SturdyDataParams,
+};
+
+sol! {
+    /// `IDebtManager.StrategyAllocation`.
+    struct StrategyAllocation {
+        address strategy;
+        uint256 debt;
+    }
+
+    /// `IVault.StrategyParams`.
+    struct VaultStrategyParams {
+        uint256 activation;
+        uint256 last_report;
+        uint256 current_debt;
+        uint256 max_debt;
+    }
+
+    /// `ZKOptimalAllocation.SturdyStrategyDataParams`.
+    struct SturdyStrategyDataParams {
+        uint256 curTimestamp;
+        uint256 lastTimestamp;
+        uint256 ratePerSec;
+        uint256 fullUtilizationRate;
+        uint256 totalAsset;
+        uint256 totalBorrow;
+        uint256 UTIL_PREC;
+        uint256 MIN_TARGET_UTIL;
+        uint256 MAX_TARGET_UTIL;
+        uint256 VERTEX_UTILIZATION;
+        uint256 MIN_FULL_UTIL_RATE;
+        uint256 MAX_FULL_UTIL_RATE;
+        uint256 ZERO_UTIL_RATE;
+        uint256 RATE_HALF_LIFE;
+        uint256 VERTEX_RATE_PERCENT;
+        uint256 RATE_PREC;
+        bool isInterestPaused;
+        uint256 feeToProtocolRate;
+    }
+
+    /// `ZKOptimalAllocation.AllocationConstraints`.
+    struct AllocationConstraints {
+        uint256[] minDebts;
+        uint256 maxShareBps;
+        uint256 minIdle;
+        bool limitWithdrawals;
+    }
+
+    /// `ZKOptimalAllocation.CostModel`.
+    struct RebalanceCostModel {
+        uint256 gasCost;
+        uint256 assetPrice;
+        uint256 holdingHorizon;
+        uint256 minNetGain;
+    }
+
+    /// `ZKOptimalAllocation.RebalanceAction`.
+    struct RebalanceAction {
+        address strategy;
+        uint8 direction;
+        uint256 amount;
+    }
+
+    /// `ZKOptimalAllocation.Performance`.
+    struct Performance {
+        uint256 newAPR;
+        uint256 curAPR;
+        uint256 newInterest;
+        uint256 curInterest;
+    }
+
+    /// `ZKOptimalAllocation.StrategyReport`.
+    struct StrategyReport {
+        address strategy;
+        uint256 currentDebt;
+        uint256 targetDebt;
+        uint256 utilPrec;
+        uint256 currentUtilization;
+        uint256 projectedUtilization;
+        uint256 currentRatePerSec;
+        uint256 projectedRatePerSec;
+        uint256 currentAPR;
+        uint256 projectedAPR;
+    }
+
+    /// `ZKOptimalAllocation.InputSnapshot`.
+    struct InputSnapshot {
+        address vault;
+        uint256 timestamp;
+    }
+
+    /// `ZKOptimalAllocation.RateModelParams`.
+    struct RateModelParams {
+        uint8 kind;
+        uint256 baseRate;
+        uint256 slope1;
+        uint256 slope2;
+        uint256 kink;
+    }
+
+    /// `ZKOptimalAllocation.Snapshot`.
+    struct Snapshot {
+        address vault;
+        bytes32 inputHash;
+        uint256 timestamp;
+    }
+
+    /// `ZKOptimalAllocation.VaultInput`.
+    struct VaultInput {
+        // Solver bisection step limit, raised to at least 64 by the guest.
+        uint256 chunkCount;
+        uint256 totalInitialAmount;
+        uint256 totalAvailable;
+        StrategyAllocation[] initialDatas;
+        VaultStrategyParams[] strategyDatas;
+        SturdyStrategyDataParams[] sturdyDatas;
+        uint8 objective;
+        AllocationConstraints constraints;
+        RebalanceCostModel costModel;
+        InputSnapshot snapshot;
+        RateModelParams[] rateModels;
+        uint256 rateHorizon;
+    }
+
+    /// `ZKOptimalAllocation.VaultResult`.
+    struct VaultResult {
+        StrategyAllocation[] allocations;
+        RebalanceAction[] actions;
+        Performance performance;
+        AllocationConstraints constraints;
+        int256 netGain;
+        uint256 unallocatedAmount;
+        bool isSuccess;
+        uint8 errorCode;
+        Snapshot snapshot;
+        StrategyReport[] reports;
+    }
+
+    /// Arguments of `ZKOptimalAllocation.onResult`.
+    struct OptimalAllocationJournal {
+        VaultResult[] results;
+    }
+}
+
+/// Values encoded by `ZKOptimalAllocation.optimalAllocationInput`.
+///
+/// Spelled as a parameter tuple rather than a `sol!` struct: nesting
+/// `VaultInput` in another struct exceeds the number of custom types `sol!`
+/// resolves per definition.
+type OptimalAllocationInput = (sol_data::Array<VaultInput>,);
+
+/// Compute the results committed by the guest for the ABI encoded `data`, one
+/// per vault in input order.
+///
+/// Failures are returned as [AllocationResult::failure], still bound to the
+/// input so that the callback can match them with their request. A vault
+/// whose fields cannot be converted fails on its own, like a vault failing
+/// validation, and the other vaults are still allocated. Only an input that
+/// cannot be decoded at all, or lists no vault, yields a single failure.
+pub fn evaluate(data: &[u8]) -> Vec<AllocationResult> {
+    let mut results = match decode_vaults(data) {
+        Ok(vaults) => allocate_in_order(vaults.into_iter().map(|vault| {
+            let snapshot = vault.snapshot.clone();
+            AllocationInput::try_from(vault)
+                .map_err(|error| vault_failure(error, snapshot.vault, snapshot.timestamp))
+        })),
+        Err(error) => vec![AllocationResult::failure(error)],
+    };
+    let input_hash = keccak256(data);
+    for result in &mut results {
+        result.input_hash = input_hash;
+    }
+    results
+}
+
+/// Decode the guest input sent by `ZKOptimalAllocation`, failing when it
+/// lists no vault or any of its vaults cannot be converted.
+pub fn decode_input(data: &[u8]) -> Result<Vec<AllocationInput>, AllocationError> {
+    decode_vaults(data)?
+        .into_iter()
+        .map(AllocationInput::try_from)
+        .collect()
+}
+
+/// Decode the raw vault inputs of `data`, failing when it lists no vault or
+/// is not encoded the way [encode_input] does.
+fn decode_vaults(data: &[u8]) -> Result<Vec<VaultInput>, AllocationError> {
+    let (vaults,) = OptimalAllocationInput::abi_decode_params(data, false)
+        .map_err(|_| AllocationError::InvalidInput)?;
+    if vaults.is_empty() || encode_vaults(&vaults) != data {
+        return Err(AllocationError::InvalidInput);
+    }
+    Ok(vaults)
+}
+
+/// Encode `inputs` the way `ZKOptimalAllocation.optimalAllocationInput` does.
+pub fn encode_input(inputs: &[AllocationInput]) -> Vec<u8> {
+    let vaults: Vec<VaultInput> = inputs.iter().map(VaultInput::from).collect();
+    encode_vaults(&vaults)
+}
+
+fn encode_vaults(vaults: &[VaultInput]) -> Vec<u8> {
+    encode_array_param(vaults.iter().map(VaultInput::abi_encode_params))
+}
+
+/// Encode `results` as the journal expected by `ZKOptimalAllocation.onResult`.
+pub fn encode_journal(results: &[AllocationResult]) -> Vec<u8> {
+    encode_array_param(
+        results
+            .iter()
+            .map(|result| VaultResult::abi_encode_params(&VaultResult::from(result))),
+    )
+}
+
+/// Decode a journal committed by the guest, failing when it is not encoded
+/// the way [encode_journal] does.
+pub fn decode_journal(data: &[u8]) -> Result<OptimalAllocationJournal, AllocationError> {
+    let journal = OptimalAllocationJournal::abi_decode_params(data, false)
+        .map_err(|_| AllocationError::InvalidInput)?;
+    let encoded = encode_array_param(journal.results.iter().map(VaultResult::abi_encode_params));
+    if encoded != data {
+        return Err(AllocationError::InvalidInput);
+    }
+    Ok(journal)
+}
+
+/// ABI encode a dynamic array of structs, given the encoding of each
+/// element, as the only parameter of a call.
+///
+/// alloy-sol-types 0.4.2 leaves the offset word of a nested dynamic struct
+/// out of the size of its parent, so it misplaces every element after the
+/// first of an array of `VaultInput` or `VaultResult`, whose constraints are
+/// such a struct, and rejects the correct encoding when validating. A single
+/// element encodes correctly, so the array offsets are written here.
+fn encode_array_param(elements: impl ExactSizeIterator<Item = Vec<u8>>) -> Vec<u8> {
+    let mut heads = Vec::with_capacity(32 * (2 + elements.len()));
+    heads.extend_from_slice(&U256::from(32).to_be_bytes::<32>());
+    heads.extend_from_slice(&U256::from(elements.len()).to_be_bytes::<32>());
+    let mut offset = 32 * elements.len();
+    let mut tails = Vec::new();
+    for element in elements {
+        heads.extend_from_slice(&U256::from(offset).to_be_bytes::<32>());
+        offset += element.len();
+        tails.extend(element);
+    }
+    heads.extend(tails);
+    heads
+}
+
+impl TryFrom<VaultInput> for AllocationInput {
+    type Error = AllocationError;
+
+    fn try_from(input: VaultInput) -> Result<Self, Self::Error> {
+        Ok(Self {
+            chunk_count: input
+                .chunkCount
+                .try_into()
+                .map_err(|_| AllocationError::InvalidInput)?,
+            total_initial_amount: input.totalInitialAmount,
+            total_available_amount: input.totalAvailable,
+            initial_datas: input.initialDatas.into_iter().map(Position::from).collect(),
+            strategy_datas: input
+                .strategyDatas
+                .into_iter()
+                .map(StrategyParams::from)
+                .collect(),
+            sturdy_datas: input
+                .sturdyDatas
+                .into_iter()
+                .map(SturdyDataParams::from)
+                .collect(),
+            objective: Objective::try_from(input.objective)?,
+            constraints: input.constraints.into(),
+            cost_model: input.costModel.into(),
+            vault: input.snapshot.vault,
+            snapshot_timestamp: input.snapshot.timestamp,
+            rate_models: input
+                .rateModels
+                .into_iter()
+                .map(rate_model::RateModelParams::try_from)
+                .collect::<Result<_, _>>()?,
+            rate_horizon: input.rateHorizon,
+        })
+    }
+}
+
+impl From<&AllocationInput> for VaultInput {
+    fn from(input: &AllocationInput) -> Self {
+        Self {
+            chunkCount: U256::from(input.chunk_count),
+            totalInitialAmount: input.total_initial_amount,
+            totalAvailable: input.total_available_amount,
+            initialDatas: input
+                .initial_datas
+                .iter()
+                .map(|position| StrategyAllocation {
+                    strategy: position.strategy,
+                    debt: position.debt,
+                })
+                .collect(),
+            strategyDatas: input
+                .strategy_datas
+                .iter()
+                .map(|params| VaultStrategyParams {
+                    activation: params.activation,
+                    last_report: params.last_report,
+                    current_debt: params.current_debt,
+                    max_debt: params.max_debt,
+                })
+                .collect(),
+            sturdyDatas: input
+                .sturdy_datas
+                .iter()
+                .map(|params| SturdyStrategyDataParams {
+                    curTimestamp: params.cur_timestamp,
+                    lastTimestamp: params.last_timestamp,
+                    ratePerSec: params.rate_per_sec,
+                    fullUtilizationRate: params.full_utilization_rate,
+                    totalAsset: params.total_asset,
+                    totalBorrow: params.total_borrow,
+                    UTIL_PREC: params.util_prec,
+                    MIN_TARGET_UTIL: params.min_target_util,
+                    MAX_TARGET_UTIL: params.max_target_util,
+                    VERTEX_UTILIZATION: params.vertex_utilization,
+                    MIN_FULL_UTIL_RATE: params.min_full_util_rate,
+                    MAX_FULL_UTIL_RATE: params.max_full_util_rate,
+                    ZERO_UTIL_RATE: params.zero_util_rate,
+                    RATE_HALF_LIFE: params.rate_half_life,
+                    VERTEX_RATE_PERCENT: params.vertex_rate_percent,
+                    RATE_PREC: params.rate_prec,
+                    isInterestPaused: params.is_interest_paused,
+                    feeToProtocolRate: params.fee_to_protocol_rate,
+                })
+                .collect(),
+            objective: input.objective as u8,
+            constraints: (&input.constraints).into(),
+            costModel: RebalanceCostModel {
+                gasCost: input.cost_model.gas_cost,
+                assetPrice: input.cost_model.asset_price,
+                holdingHorizon: input.cost_model.holding_horizon,
+                minNetGain: input.cost_model.min_net_gain,
+            },
+            snapshot: InputSnapshot {
+                vault: input.vault,
+                timestamp: input.snapshot_timestamp,
+            },
+            rateModels: input
+                .rate_models
+                .iter()
+                .map(|params| RateModelParams {
+                    kind: params.kind as u8,
+                    baseRate: params.base_rate,
+                    slope1: params.slope1,
+                    slope2: params.slope2,
+                    kink: params.kink,
+                })
+                .collect(),
+            rateHorizon: input.rate_horizon,
+        }
+    }
+}
+
+impl From<&AllocationResult> for VaultResult {
+    fn from(result: &AllocationResult) -> Self {
+        Self {
+            allocations: result
+                .allocations
+                .iter()
+                .map(|position| StrategyAllocation {
+                    strategy: position.strategy,
+                    debt: position.debt,
+                })
+                .collect(),
+            actions: result
+                .actions
+                .iter()
+                .map(|action| RebalanceAction {
+                    strategy: action.strategy,
+                    direction: action.direction as u8,
+                    amount: action.amount,
+                })
+                .collect(),
+            performance: Performance {
+                newAPR: U256::from(result.new_apr),
+                curAPR: U256::from(result.current_apr),
+                newInterest: result.new_interest,
+                curInterest: result.current_interest,
+            },
+            constraints: (&result.constraints).into(),
+            netGain: result.net_gain,
+            unallocatedAmount: result.unallocated_amount,
+            isSuccess: result.is_success,
+            errorCode: result.error_code,
+            snapshot: Snapshot {
+                vault: result.vault,
+                inputHash: result.input_hash,
+                timestamp: result.snapshot_timestamp,
+            },
+            reports: result
+                .reports
+                .iter()
+                .map(|report| StrategyReport {
+                    strategy: report.strategy,
+                    currentDebt: report.current_debt,
+                    targetDebt: report.target_debt,
+                    utilPrec: report.util_prec,
+                    currentUtilization: report.current_utilization,
+                    projectedUtilization: report.projected_utilization,
+                    currentRatePerSec: U256::from(report.current_rate_per_sec),
+                    projectedRatePerSec: U256::from(report.projected_rate_per_sec),
+                    currentAPR: report.current_apr,
+                    projectedAPR: report.projected_apr,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<StrategyAllocation> for Position {
+    fn from(allocation: StrategyAllocation) -> Self {
+        Self {
+            strategy: allocation.strategy,
+            debt: allocation.debt,
+        }
+    }
+}
+
+impl From<VaultStrategyParams> for StrategyParams {
+    fn from(params: VaultStrategyParams) -> Self {
+        Self {
+            activation: params.activation,
+            last_report: params.last_report,
+            current_debt: params.current_debt,
+            max_debt: params.max_debt,
+        }
+    }
+}
+
+impl From<SturdyStrategyDataParams> for SturdyDataParams {
+    fn from(params: SturdyStrategyDataParams) -> Self {
+        Self {
+            cur_timestamp: params.curTimestamp,
+            last_timestamp: params.lastTimestamp,
+            rate_per_sec: params.ratePerSec,
+            full_utilization_rate: params.fullUtilizationRate,
+            total_asset: params.totalAsset,
+            total_borrow: params.totalBorrow,
+            util_prec: params.UTIL_PREC,
+            min_target_util: params.MIN_TARGET_UTIL,
+            max_target_util: params.MAX_TARGET_UTIL,
+            vertex_utilization: params.VERTEX_UTILIZATION,
+            min_full_util_rate: params.MIN_FULL_UTIL_RATE,
+            max_full_util_rate: params.MAX_FULL_UTIL_RATE,
+            zero_util_rate: params.ZERO_UTIL_RATE,
+            rate_half_life: params.RATE_HALF_LIFE,
+            vertex_rate_percent: params.VERTEX_RATE_PERCENT,
+            rate_prec: params.RATE_PREC,
+            is_interest_paused: params.isInterestPaused,
+            fee_to_protocol_rate: params.feeToProtocolRate,
+        }
+    }
+}
+
+impl From<AllocationConstraints> for Constraints {
+    fn from(constraints: AllocationConstraints) -> Self {
+        Self {
+            min_debts: constraints.minDebts,
+            max_share_bps: constraints.maxShareBps,
+            min_idle: constraints.minIdle,
+            limit_withdrawals: constraints.limitWithdrawals,
+        }
+    }
+}
+
+impl From<&Constraints> for AllocationConstraints {
+    fn from(constraints: &Constraints) -> Self {
+        Self {
+            minDebts: constraints.min_debts.clone(),
+            maxShareBps: constraints.max_share_bps,
+            minIdle: constraints.min_idle,
+            limitWithdrawals: constraints.limit_withdrawals,
+        }
+    }
+}
+
+impl TryFrom<RateModelParams> for rate_model::RateModelParams {
+    type Error = AllocationError;
+
+    fn try_from(params: RateModelParams) -> Result<Self, Self::Error> {
+        Ok(Self {
+            kind: RateModelKind::try_from(params.kind)?,
+            base_rate: params.baseRate,
+            slope1: params.slope1,
+            slope2: params.slope2,
+            kink: params.kink,
+        })
+    }
+}
+
+impl From<RebalanceCostModel> for CostModel {
+    fn from(cost_model: RebalanceCostModel) -> Self {
+        Self {
+            gas_cost: cost_model.gasCost,
+            asset_price: cost_model.assetPrice,
+            holding_horizon: cost_model.holdingHorizon,
+            min_net_gain: cost_model.minNetGain,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::Address;
+
+    use super::*;
+    use crate::fixtures::{mainnet_input, units};
+
+    #[test]
+    fn round_trips_inputs() -> Result<(), AllocationError> {
+        let input = mainnet_input(units(14000));
+        let decoded = decode_input(&encode_input(&[input.clone()]))?;
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].initial_datas, input.initial_datas);
+        assert_eq!(
+            decoded[0].total_available_amount,
+            input.total_available_amount
+        );
+        assert_eq!(decoded[0].vault, input.vault);
+        Ok(())
+    }
+
+    #[test]
+    fn offsets_array_elements_past_the_previous_ones() -> Result<(), AllocationError> {
+        let first = VaultInput::from(&mainnet_input(units(14000)));
+        let mut second = first.clone();
+        second.constraints.minDebts = vec![U256::from(1); 4];
+        let first_len = VaultInput::abi_encode_params(&first).len();
+        let data = encode_vaults(&[first, second]);
+        assert_eq!(
+            U256::from_be_slice(&data[96..128]),
+            U256::from(64 + first_len)
+        );
+
+        let decoded = decode_input(&data)?;
+        assert_eq!(decoded[0].constraints.min_debts, vec![]);
+        assert_eq!(decoded[1].constraints.min_debts, vec![U256::from(1); 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_multi_vault_journals() -> Result<(), AllocationError> {
+        let input = mainnet_input(units(14000));
+        let results = evaluate(&encode_input(&[input.clone(), input]));
+        let journal = decode_journal(&encode_journal(&results))?;
+        assert_eq!(journal.results.len(), 2);
+        for (decoded, result) in journal.results.iter().zip(&results) {
+            assert_eq!(decoded.isSuccess, result.is_success);
+            assert_eq!(decoded.allocations.len(), result.allocations.len());
+            assert_eq!(decoded.reports.len(), result.reports.len());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fails_bad_vaults_on_their_own() {
+        let good = mainnet_input(units(14000));
+        let mut bad_objective = VaultInput::from(&good);
+        bad_objective.objective = 9;
+        bad_objective.snapshot.vault = Address::with_last_byte(0xbb);
+        let mut bad_rate_model = VaultInput::from(&good);
+        bad_rate_model.rateModels = (0..4)
+            .map(|i| RateModelParams {
+                kind: if i == 2 {
+                    9
+                } else {
+                    RateModelKind::Sturdy as u8
+                },
+                baseRate: U256::ZERO,
+                slope1: U256::ZERO,
+                slope2: U256::ZERO,
+                kink: U256::ZERO,
+            })
+            .collect();
+        bad_rate_model.snapshot.vault = Address::with_last_byte(0xcc);
+        let data = encode_vaults(&[bad_objective, VaultInput::from(&good), bad_rate_model]);
+
+        assert!(decode_input(&data).is_err());
+        let results = evaluate(&data);
+        assert_eq!(results.len(), 3);
+        let code = AllocationError::InvalidInput.code();
+        assert_eq!(
+            (results[0].vault, results[0].error_code),
+            (Address::with_last_byte(0xbb), code)
+        );
+        assert_eq!(results[0].snapshot_timestamp, good.snapshot_timestamp);
+        assert!(results[1].is_success);
+        assert_eq!(results[1].vault, good.vault);
+        assert_eq!(
+            (results[2].vault, results[2].error_code),
+            (Address::with_last_byte(0xcc), code)
+        );
+        assert!(results
+            .iter()
+            .all(|result| result.input_hash == keccak256(&data)));
+    }
+
+    #[test]
+    fn fails_undecodable_input_once() {
+        for data in [vec![0u8; 3], encode_vaults(&[])] {
+            let results = evaluate(&data);
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].error_code, AllocationError::InvalidInput.code());
+            assert_eq!(results[0].vault, Address::ZERO);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ordered debt moves taking the vault from its current debts to an
+//! allocation.
+
+use alloc::vec::Vec;
+use core::cmp::{Ordering, Reverse};
+
+use alloy_primitives::{Address, U256};
+
+use crate::{
+    error::{add, sub, AllocationError},
+    optimizer::{AllocationInput, Position},
+};
+
+/// Direction of a debt move, mirroring `ZKOptimalAllocation.RebalanceDirection`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Direction {
+    /// Lower the debt of the strategy, returning assets to the vault.
+    Withdraw = 0,
+    /// Raise the debt of the strategy with assets held by the vault.
+    Deposit = 1,
+}
+
+/// Debt move of a single strategy, mirroring
+/// `ZKOptimalAllocation.RebalanceAction`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebalanceAction {
+    pub strategy: Address,
+    pub direction: Direction,
+    /// Assets moved, always positive.
+    pub amount: U256,
+}
+
+/// Moves taking every strategy from its current debt to its debt in
+/// `allocations`, in execution order.
+///
+/// Withdrawals come first, largest first so that liquidity is freed as early
+/// as possible, followed by the deposits, largest first. Equal amounts keep
+/// their order in `allocations`. Each deposit is funded by the withdrawals
+/// before it and by the idle assets of the vault, taken to be
+/// `total_available_amount` minus the current debt of the listed strategies.
+/// Strategies whose debt does not change get no action.
+///
+/// Fails with [AllocationError::InfeasibleConstraints] when those funds do not
+/// cover the deposits, since the actions could then not reach `allocations`.
+pub fn rebalance_actions(
+    input: &AllocationInput,
+    allocations: &[Position],
+) -> Result<Vec<RebalanceAction>, AllocationError> {
+    let mut withdrawals = Vec::new();
+    let mut deposits = Vec::new();
+    for position in allocations {
+        let index = input
+            .initial_datas
+            .iter()
+            .position(|initial_data| initial_data.strategy == position.strategy)
+            .ok_or(AllocationError::InvalidInput)?;
+        let current_debt = input.strategy_datas[index].current_debt;
+
+        match position.debt.cmp(&current_debt) {
+            Ordering::Less => withdrawals.push(RebalanceAction {
+                strategy: position.strategy,
+                direction: Direction::Withdraw,
+                amount: sub(current_debt, position.debt)?,
+            }),
+            Ordering::Greater => deposits.push(RebalanceAction {
+                strategy: position.strategy,
+                direction: Direction::Deposit,
+                amount: sub(position.debt, current_debt)?,
+            }),
+            Ordering::Equal => {}
+        }
+    }
+    // Stable sorts, so ties keep their allocation order.
+    withdrawals.sort_by_key(|action| Reverse(action.amount));
+    deposits.sort_by_key(|action| Reverse(action.amount));
+
+    let mut total_debt = U256::from(0);
+    for strategy_data in &input.strategy_datas {
+        total_debt = add(total_debt, strategy_data.current_debt)?;
+    }
+    let mut funds = input.total_available_amount.saturating_sub(total_debt);
+    for withdrawal in &withdrawals {
+        funds = add(funds, withdrawal.amount)?;
+    }
+    for deposit in &deposits {
+        funds = funds
+            .checked_sub(deposit.amount)
+            .ok_or(AllocationError::InfeasibleConstraints)?;
+    }
+
+    withdrawals.extend(deposits);
+    Ok(withdrawals)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::fixtures::{market, strategy, units, vault};
+
+    /// Vault of `total_available` units whose strategies currently hold
+    /// 1000, 3000, 0 and 1000.
+    fn input(total_available: u64) -> AllocationInput {
+        let mut input = vault(units(total_available), &[(market(1000, 0, 0), 6000); 4]);
+        for (strategy_data, debt) in input.strategy_datas.iter_mut().zip([1000, 3000, 0, 1000]) {
+            strategy_data.current_debt = units(debt);
+        }
+        input
+    }
+
+    fn position(i: usize, debt: u64) -> Position {
+        Position {
+            strategy: strategy(i),
+            debt: units(debt),
+        }
+    }
+
+    fn action(i: usize, direction: Direction, amount: u64) -> RebalanceAction {
+        RebalanceAction {
+            strategy: strategy(i),
+            direction,
+            amount: units(amount),
+        }
+    }
+
+    #[test]
+    fn withdraws_then_deposits_largest_first() -> Result<(), AllocationError> {
+        let allocations = [
+            position(0, 0),
+            position(1, 1000),
+            position(3, 3500),
+            position(2, 1500),
+        ];
+        assert_eq!(
+            rebalance_actions(&input(6000), &allocations)?,
+            vec![
+                action(1, Direction::Withdraw, 2000),
+                action(0, Direction::Withdraw, 1000),
+                action(3, Direction::Deposit, 2500),
+                action(2, Direction::Deposit, 1500),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_allocation_order_for_equal_amounts() -> Result<(), AllocationError> {
+        let allocations = [
+            position(3, 0),
+            position(2, 1000),
+            position(0, 0),
+            position(1, 4000),
+        ];
+        assert_eq!(
+            rebalance_actions(&input(5000), &allocations)?,
+            vec![
+                action(3, Direction::Withdraw, 1000),
+                action(0, Direction::Withdraw, 1000),
+                action(2, Direction::Deposit, 1000),
+                action(1, Direction::Deposit, 1000),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_unchanged_debts() -> Result<(), AllocationError> {
+        let allocations = [position(0, 1000), position(2, 500)];
+        assert_eq!(
+            rebalance_actions(&input(5500), &allocations)?,
+            vec![action(2, Direction::Deposit, 500)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn funds_deposits_from_idle_assets_and_withdrawals() -> Result<(), AllocationError> {
+        // 500 idle units and 1000 withdrawn fund exactly 1500 of deposits.
+        let allocations = [position(0, 0), position(2, 1500)];
+        assert_eq!(
+            rebalance_actions(&input(5500), &allocations)?,
+            vec![
+                action(0, Direction::Withdraw, 1000),
+                action(2, Direction::Deposit, 1500),
+            ]
+        );
+
+        let mut allocations = allocations;
+        allocations[1].debt += U256::from(1);
+        assert_eq!(
+            rebalance_actions(&input(5500), &allocations),
+            Err(AllocationError::InfeasibleConstraints)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_strategies() {
+        assert_eq!(
+            rebalance_actions(&input(5000), &[position(4, 0)]),
+            Err(AllocationError::InvalidInput)
+        );
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vault policy limits enforced on top of each strategy's `max_debt`.
+
+use alloc::vec::Vec;
+
+use alloy_primitives::U256;
+
+use crate::{
+    error::{add, div, mul, sub, AllocationError},
+    optimizer::AllocationInput,
+};
+
+/// Basis points in one.
+const MAX_BPS: u64 = 10_000;
+
+/// Optional allocation constraints, mirroring
+/// `ZKOptimalAllocation.AllocationConstraints`. The default value enforces
+/// nothing beyond `max_debt`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Constraints {
+    /// Minimum debt of each strategy, in input order. Empty when unset.
+    pub min_debts: Vec<U256>,
+    /// Maximum debt of a single strategy, in basis points of the total
+    /// available amount. Zero when unset.
+    pub max_share_bps: U256,
+    /// Assets left idle in the vault on top of its `minimum_total_idle`.
+    pub min_idle: U256,
+    /// Never withdraw more from a strategy than its pair can pay out, that is
+    /// `total_asset - total_borrow`.
+    pub limit_withdrawals: bool,
+}
+
+/// Range of debt a strategy may end up with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct DebtBounds {
+    pub floor: U256,
+    pub cap: U256,
+}
+
+/// Debt the strategies can take on top of their floors.
+pub(crate) fn capacity(bounds: &[DebtBounds]) -> Result<U256, AllocationError> {
+    let mut total = U256::from(0);
+    for bounds in bounds {
+        total = add(total, sub(bounds.cap, bounds.floor)?)?;
+    }
+    Ok(total)
+}
+
+/// Debt bounds of every strategy and the amount to distribute on top of the
+/// floors.
+pub(crate) fn debt_bounds(
+    input: &AllocationInput,
+) -> Result<(Vec<DebtBounds>, U256), AllocationError> {
+    let constraints = &input.constraints;
+    let strategy_count = input.initial_datas.len();
+    if !constraints.min_debts.is_empty() && constraints.min_debts.len() != strategy_count {
+        return Err(AllocationError::InvalidInput);
+    }
+    if constraints.max_share_bps > U256::from(MAX_BPS) {
+        return Err(AllocationError::InvalidInput);
+    }
+
+    let share_cap = if constraints.max_share_bps != U256::from(0) {
+        Some(div(
+            mul(input.total_available_amount, constraints.max_share_bps)?,
+            U256::from(MAX_BPS),
+        )?)
+    } else {
+        None
+    };
+
+    let mut bounds = Vec::with_capacity(strategy_count);
+    let mut raised = U256::from(0);
+    for (j, (initial_data, strategy_data)) in input
+        .initial_datas
+        .iter()
+        .zip(&input.strategy_datas)
+        .enumerate()
+    {
+        let mut floor = initial_data.debt;
+        if let Some(min_debt) = constraints.min_debts.get(j) {
+            floor = floor.max(*min_debt);
+        }
+        if constraints.limit_withdrawals {
+            let sturdy_data = input
+                .sturdy_datas
+                .get(j)
+                .ok_or(AllocationError::InvalidInput)?;
+            let liquidity = sturdy_data
+                .total_asset
+                .saturating_sub(sturdy_data.total_borrow);
+            floor = floor.max(strategy_data.current_debt.saturating_sub(liquidity));
+        }
+
+        // Initial debts above the limits are kept rather than rejected.
+        let mut cap = strategy_data.max_debt;
+        if let Some(share_cap) = share_cap {
+            cap = cap.min(share_cap);
+        }
+        cap = cap.max(initial_data.debt);
+        if floor > cap {
+            return Err(AllocationError::InfeasibleConstraints);
+        }
+
+        raised = add(raised, sub(floor, initial_data.debt)?)?;
+        bounds.push(DebtBounds { floor, cap });
+    }
+
+    let amount = input
+        .total_available_amount
+        .checked_sub(input.total_initial_amount)
+        .ok_or(AllocationError::InvalidInput)?
+        .checked_sub(constraints.min_idle)
+        .and_then(|amount| amount.checked_sub(raised))
+        .ok_or(AllocationError::InfeasibleConstraints)?;
+
+    Ok((bounds, amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::fixtures::{market, units, vault};
+
+    /// 10000 units over three strategies, the first lending to a market with
+    /// 2000 units of liquidity.
+    fn input(constraints: Constraints) -> AllocationInput {
+        let mut input = vault(
+            units(10000),
+            &[
+                (market(10000, 8000, 0), 6000),
+                (market(10000, 2000, 0), 5000),
+                (market(1000, 900, 0), 3000),
+            ],
+        );
+        input.constraints = constraints;
+        input
+    }
+
+    fn floors_and_caps(bounds: &[DebtBounds]) -> (Vec<U256>, Vec<U256>) {
+        bounds
+            .iter()
+            .map(|bounds| (bounds.floor, bounds.cap))
+            .unzip()
+    }
+
+    #[test]
+    fn defaults_to_max_debts() -> Result<(), AllocationError> {
+        let (bounds, amount) = debt_bounds(&input(Constraints::default()))?;
+        assert_eq!(
+            floors_and_caps(&bounds),
+            (vec![U256::ZERO; 3], [6000, 5000, 3000].map(units).to_vec())
+        );
+        assert_eq!(amount, units(10000));
+        assert_eq!(capacity(&bounds)?, units(14000));
+        Ok(())
+    }
+
+    #[test]
+    fn raises_floors_to_min_debts() -> Result<(), AllocationError> {
+        let (bounds, amount) = debt_bounds(&input(Constraints {
+            min_debts: [1000, 0, 3000].map(units).to_vec(),
+            min_idle: units(2000),
+            ..Constraints::default()
+        }))?;
+        assert_eq!(floors_and_caps(&bounds).0, [1000, 0, 3000].map(units));
+        assert_eq!(amount, units(4000));
+
+        let unmet = input(Constraints {
+            min_debts: [1000, 0, 3001].map(units).to_vec(),
+            ..Constraints::default()
+        });
+        assert_eq!(
+            debt_bounds(&unmet),
+            Err(AllocationError::InfeasibleConstraints)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_min_idle_out_of_the_amount() -> Result<(), AllocationError> {
+        let all_idle = input(Constraints {
+            min_idle: units(10000),
+            ..Constraints::default()
+        });
+        assert_eq!(debt_bounds(&all_idle)?.1, U256::ZERO);
+
+        let mut over = all_idle;
+        over.constraints.min_idle += U256::from(1);
+        assert_eq!(
+            debt_bounds(&over),
+            Err(AllocationError::InfeasibleConstraints)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn caps_shares_above_initial_debts() -> Result<(), AllocationError> {
+        let mut shared = input(Constraints {
+            max_share_bps: U256::from(2500),
+            ..Constraints::default()
+        });
+        shared.initial_datas[1].debt = units(4000);
+        shared.total_initial_amount = units(4000);
+        let (bounds, amount) = debt_bounds(&shared)?;
+        assert_eq!(floors_and_caps(&bounds).1, [2500, 4000, 2500].map(units));
+        assert_eq!(amount, units(6000));
+
+        shared.constraints.max_share_bps = U256::from(MAX_BPS + 1);
+        assert_eq!(debt_bounds(&shared), Err(AllocationError::InvalidInput));
+        Ok(())
+    }
+
+    #[test]
+    fn limits_withdrawals_to_pair_liquidity() -> Result<(), AllocationError> {
+        let mut limited = input(Constraints {
+            limit_withdrawals: true,
+            ..Constraints::default()
+        });
+        limited.strategy_datas[0].current_debt = units(5000);
+        let (bounds, amount) = debt_bounds(&limited)?;
+        assert_eq!(floors_and_caps(&bounds).0, [3000, 0, 0].map(units));
+        assert_eq!(amount, units(7000));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_inconsistent_inputs() {
+        let short = input(Constraints {
+            min_debts: vec![U256::ZERO],
+            ..Constraints::default()
+        });
+        assert_eq!(debt_bounds(&short), Err(AllocationError::InvalidInput));
+
+        let mut overdrawn = input(Constraints::default());
+        overdrawn.total_initial_amount = units(10000) + U256::from(1);
+        assert_eq!(debt_bounds(&overdrawn), Err(AllocationError::InvalidInput));
+    }
+}
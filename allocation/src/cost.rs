@@ -0,0 +1,127 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cost of executing a rebalance, weighed against the interest it earns.
+
+use alloy_primitives::{I256, U256};
+
+use crate::{
+    error::{div, mul, signed_div, signed_mul, signed_sub, to_i256, AllocationError},
+    sturdy::{APR_PRECISION, SECONDS_PER_YEAR},
+};
+
+/// Rebalance cost model, mirroring `ZKOptimalAllocation.CostModel`. It is
+/// disabled while `holding_horizon` is zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CostModel {
+    /// Expected gas cost of the rebalance, in wei.
+    pub gas_cost: U256,
+    /// Price of one asset base unit in wei, scaled by 1e18.
+    pub asset_price: U256,
+    /// Seconds the new allocation is expected to stay in place.
+    pub holding_horizon: U256,
+    /// Net gain, in asset base units, the rebalance must beat.
+    pub min_net_gain: U256,
+}
+
+impl CostModel {
+    pub fn is_enabled(&self) -> bool {
+        self.holding_horizon != U256::from(0)
+    }
+
+    /// Gas cost converted to asset base units.
+    pub fn gas_cost_in_asset(&self) -> Result<U256, AllocationError> {
+        if self.gas_cost == U256::from(0) {
+            return Ok(U256::from(0));
+        }
+        if self.asset_price == U256::from(0) {
+            return Err(AllocationError::InvalidInput);
+        }
+        div(
+            mul(self.gas_cost, U256::from(APR_PRECISION))?,
+            self.asset_price,
+        )
+    }
+
+    /// Interest gained over the holding horizon, going from `current_interest`
+    /// to `new_interest` per year, minus the gas cost.
+    pub fn net_gain(
+        &self,
+        current_interest: U256,
+        new_interest: U256,
+    ) -> Result<I256, AllocationError> {
+        let seconds_per_year = to_i256(U256::from(SECONDS_PER_YEAR))?;
+        let interest_gain = signed_div(
+            signed_mul(
+                signed_sub(to_i256(new_interest)?, to_i256(current_interest)?)?,
+                to_i256(self.holding_horizon)?,
+            )?,
+            seconds_per_year,
+        )?;
+        signed_sub(interest_gain, to_i256(self.gas_cost_in_asset()?)?)
+    }
+
+    /// Whether a rebalance with `net_gain` is worth executing.
+    pub fn beats_threshold(&self, net_gain: I256) -> Result<bool, AllocationError> {
+        Ok(!self.is_enabled() || net_gain > to_i256(self.min_net_gain)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost_model(gas_cost: u64, asset_price: u64, holding_horizon: u64) -> CostModel {
+        CostModel {
+            gas_cost: U256::from(gas_cost),
+            asset_price: U256::from(asset_price),
+            holding_horizon: U256::from(holding_horizon),
+            min_net_gain: U256::from(100),
+        }
+    }
+
+    #[test]
+    fn converts_gas_cost_to_asset() {
+        // One asset unit is worth two wei.
+        assert_eq!(
+            cost_model(1000, 2 * APR_PRECISION, 1).gas_cost_in_asset(),
+            Ok(U256::from(500))
+        );
+        assert_eq!(cost_model(0, 0, 1).gas_cost_in_asset(), Ok(U256::ZERO));
+        assert_eq!(
+            cost_model(1000, 0, 1).gas_cost_in_asset(),
+            Err(AllocationError::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn weighs_interest_over_the_horizon_against_gas() -> Result<(), AllocationError> {
+        let year = SECONDS_PER_YEAR as u64;
+        let model = cost_model(1000, APR_PRECISION, year / 2);
+        let gain = model.net_gain(U256::from(10000), U256::from(14000))?;
+        assert_eq!(gain, I256::from_raw(U256::from(1000)));
+        let loss = model.net_gain(U256::from(14000), U256::from(10000))?;
+        assert_eq!(loss, -I256::from_raw(U256::from(3000)));
+        Ok(())
+    }
+
+    #[test]
+    fn beats_threshold_only_above_min_net_gain() -> Result<(), AllocationError> {
+        let model = cost_model(0, 0, 1);
+        assert!(!model.beats_threshold(I256::from_raw(U256::from(100)))?);
+        assert!(model.beats_threshold(I256::from_raw(U256::from(101)))?);
+        assert!(cost_model(0, 0, 0).beats_threshold(I256::MINUS_ONE)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Failure modes of the optimal allocation, committed as error codes.
+
+use core::fmt;
+
+use alloy_primitives::{I256, U256};
+
+/// Reason the allocation could not be computed.
+///
+/// The discriminant is the `errorCode` committed in the journal and must stay
+/// in sync with the codes documented on `ZKOptimalAllocation.AllocationFailed`.
+/// Zero is reserved for "no error".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AllocationError {
+    /// The input could not be ABI decoded into the expected layout.
+    InvalidInput = 1,
+    /// No strategy can take the next chunk without exceeding its max debt.
+    NoAvailableStrategy = 2,
+    /// An intermediate value overflows, underflows or does not fit in its
+    /// target integer type.
+    Overflow = 3,
+    /// The allocation constraints cannot all be met, or the vault cannot fund
+    /// the deposits reaching the allocation.
+    InfeasibleConstraints = 4,
+    /// The per-strategy lists of the input do not all have one entry per
+    /// strategy.
+    LengthMismatch = 5,
+    /// A strategy is listed more than once.
+    DuplicateStrategy = 6,
+    /// `total_available_amount` is below `total_initial_amount`.
+    AvailableBelowInitial = 7,
+    /// `chunk_count` is zero.
+    ZeroChunkCount = 8,
+    /// A pair's `max_target_util` is not below its `util_prec`.
+    InvalidTargetUtilization = 9,
+    /// A pair's `cur_timestamp` is before its `last_timestamp`.
+    TimestampBeforeLastUpdate = 10,
+    /// A pair's `fee_to_protocol_rate` is above its
+    /// [FEE_PRECISION](crate::FEE_PRECISION).
+    InvalidProtocolFee = 11,
+}
+
+impl AllocationError {
+    /// Error code committed in the journal.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Error of a committed error code, `None` for zero and unknown codes.
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            1 => AllocationError::InvalidInput,
+            2 => AllocationError::NoAvailableStrategy,
+            3 => AllocationError::Overflow,
+            4 => AllocationError::InfeasibleConstraints,
+            5 => AllocationError::LengthMismatch,
+            6 => AllocationError::DuplicateStrategy,
+            7 => AllocationError::AvailableBelowInitial,
+            8 => AllocationError::ZeroChunkCount,
+            9 => AllocationError::InvalidTargetUtilization,
+            10 => AllocationError::TimestampBeforeLastUpdate,
+            11 => AllocationError::InvalidProtocolFee,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            AllocationError::InvalidInput => "input does not match the expected ABI layout",
+            AllocationError::NoAvailableStrategy => "no strategy can take the next chunk",
+            AllocationError::Overflow => "arithmetic overflow",
+            AllocationError::InfeasibleConstraints => "allocation constraints cannot all be met",
+            AllocationError::LengthMismatch => "strategy lists have different lengths",
+            AllocationError::DuplicateStrategy => "a strategy is listed more than once",
+            AllocationError::AvailableBelowInitial => {
+                "total available amount is below the total initial amount"
+            }
+            AllocationError::ZeroChunkCount => "chunk count is zero",
+            AllocationError::InvalidTargetUtilization => {
+                "max target utilization is not below the utilization precision"
+            }
+            AllocationError::TimestampBeforeLastUpdate => {
+                "pair timestamp is before its last rate update"
+            }
+            AllocationError::InvalidProtocolFee => "protocol fee is above its precision",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Narrow `value` to a `u64`.
+pub(crate) fn to_u64(value: U256) -> Result<u64, AllocationError> {
+    value.try_into().map_err(|_| AllocationError::Overflow)
+}
+
+/// Convert `value` to a signed integer.
+pub(crate) fn to_i256(value: U256) -> Result<I256, AllocationError> {
+    I256::try_from(value).map_err(|_| AllocationError::Overflow)
+}
+
+/// Convert a signed `value` back to an unsigned integer, failing when it is
+/// negative.
+pub(crate) fn to_u256(value: I256) -> Result<U256, AllocationError> {
+    U256::try_from(value).map_err(|_| AllocationError::Overflow)
+}
+
+/// `a + b`, failing on overflow.
+pub(crate) fn add(a: U256, b: U256) -> Result<U256, AllocationError> {
+    a.checked_add(b).ok_or(AllocationError::Overflow)
+}
+
+/// `a - b`, failing on underflow.
+pub(crate) fn sub(a: U256, b: U256) -> Result<U256, AllocationError> {
+    a.checked_sub(b).ok_or(AllocationError::Overflow)
+}
+
+/// `a * b`, failing on overflow.
+pub(crate) fn mul(a: U256, b: U256) -> Result<U256, AllocationError> {
+    a.checked_mul(b).ok_or(AllocationError::Overflow)
+}
+
+/// `a / b`, failing when `b` is zero.
+pub(crate) fn div(a: U256, b: U256) -> Result<U256, AllocationError> {
+    a.checked_div(b).ok_or(AllocationError::InvalidInput)
+}
+
+/// `a + b` on signed values, failing on overflow.
+pub(crate) fn signed_add(a: I256, b: I256) -> Result<I256, AllocationError> {
+    a.checked_add(b).ok_or(AllocationError::Overflow)
+}
+
+/// `a - b` on signed values, failing on overflow.
+pub(crate) fn signed_sub(a: I256, b: I256) -> Result<I256, AllocationError> {
+    a.checked_sub(b).ok_or(AllocationError::Overflow)
+}
+
+/// `a * b` on signed values, failing on overflow.
+pub(crate) fn signed_mul(a: I256, b: I256) -> Result<I256, AllocationError> {
+    a.checked_mul(b).ok_or(AllocationError::Overflow)
+}
+
+/// `a / b` on signed values, failing when `b` is zero or the quotient
+/// overflows.
+pub(crate) fn signed_div(a: I256, b: I256) -> Result<I256, AllocationError> {
+    a.checked_div(b).ok_or(AllocationError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        for code in 1..=11 {
+            let error = AllocationError::from_code(code);
+            assert_eq!(error.map(AllocationError::code), Some(code));
+        }
+        assert_eq!(AllocationError::from_code(0), None);
+        assert_eq!(AllocationError::from_code(12), None);
+    }
+
+    #[test]
+    fn checked_helpers_fail_with_their_error() {
+        let one = U256::from(1);
+        assert_eq!(add(U256::MAX, one), Err(AllocationError::Overflow));
+        assert_eq!(sub(U256::ZERO, one), Err(AllocationError::Overflow));
+        assert_eq!(
+            mul(U256::MAX, U256::from(2)),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(div(one, U256::ZERO), Err(AllocationError::InvalidInput));
+        assert_eq!(
+            to_u64(U256::from(u64::MAX) + one),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(to_i256(U256::MAX), Err(AllocationError::Overflow));
+        assert_eq!(to_u256(I256::MINUS_ONE), Err(AllocationError::Overflow));
+        assert_eq!(
+            signed_add(I256::MAX, I256::ONE),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(
+            signed_sub(I256::MIN, I256::ONE),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(
+            signed_mul(I256::MAX, I256::from_raw(U256::from(2))),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(
+            signed_div(I256::ONE, I256::ZERO),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(
+            signed_div(I256::MIN, I256::MINUS_ONE),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(signed_div(I256::MINUS_ONE, I256::ONE), Ok(I256::MINUS_ONE));
+    }
+}
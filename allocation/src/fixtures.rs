@@ -0,0 +1,176 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Inputs shared by the unit tests.
+
+use alloc::{vec, vec::Vec};
+
+use alloy_primitives::{Address, U256};
+
+use crate::{
+    constraints::Constraints,
+    cost::CostModel,
+    optimizer::{AllocationInput, Objective, Position, StrategyParams},
+    rate_model::{RateModelKind, RateModelParams},
+    sturdy::SturdyDataParams,
+};
+
+/// Timestamp of every snapshot, that of the mainnet pairs.
+const SNAPSHOT_TIMESTAMP: u64 = 1697739119;
+
+/// `n` whole asset units of 18 decimals.
+pub(crate) fn units(n: u64) -> U256 {
+    U256::from(n) * U256::from(10u64).pow(U256::from(18))
+}
+
+/// Sturdy pair with the mainnet rate curve and the given state.
+pub(crate) fn pair(
+    last_timestamp: u64,
+    rate_per_sec: u64,
+    full_utilization_rate: u64,
+    total_asset: u128,
+    total_borrow: u128,
+) -> SturdyDataParams {
+    SturdyDataParams {
+        cur_timestamp: U256::from(SNAPSHOT_TIMESTAMP),
+        last_timestamp: U256::from(last_timestamp),
+        rate_per_sec: U256::from(rate_per_sec),
+        full_utilization_rate: U256::from(full_utilization_rate),
+        total_asset: U256::from(total_asset),
+        total_borrow: U256::from(total_borrow),
+        util_prec: U256::from(100000),
+        min_target_util: U256::from(75000),
+        max_target_util: U256::from(85000),
+        vertex_utilization: U256::from(87500),
+        min_full_util_rate: U256::from(1582470460),
+        max_full_util_rate: U256::from(3164940920000u64),
+        zero_util_rate: U256::from(158247046),
+        rate_half_life: U256::from(172800),
+        vertex_rate_percent: U256::from(200000000000000000u64),
+        rate_prec: U256::from(1000000000000000000u64),
+        is_interest_paused: false,
+        fee_to_protocol_rate: U256::ZERO,
+    }
+}
+
+/// Pair lending `total_borrow` of its `total_asset` units at `rate_per_sec`,
+/// updated at the snapshot time.
+pub(crate) fn market(total_asset: u64, total_borrow: u64, rate_per_sec: u64) -> SturdyDataParams {
+    let mut sturdy_data = pair(SNAPSHOT_TIMESTAMP, rate_per_sec, 1582470460, 0, 0);
+    sturdy_data.total_asset = units(total_asset);
+    sturdy_data.total_borrow = units(total_borrow);
+    sturdy_data
+}
+
+/// Rate model paying `rate_per_sec` whatever the utilization.
+pub(crate) fn fixed(rate_per_sec: u64) -> RateModelParams {
+    RateModelParams {
+        kind: RateModelKind::Fixed,
+        base_rate: U256::from(rate_per_sec),
+        ..RateModelParams::default()
+    }
+}
+
+/// Strategy `i` of a vault, at `address(i + 1)`.
+pub(crate) fn strategy(i: usize) -> Address {
+    Address::with_last_byte(i as u8 + 1)
+}
+
+/// Vault with `total_available` units to allocate and nothing deposited yet,
+/// lending to one strategy per `(pair, max_debt)` of `strategies`.
+pub(crate) fn vault(
+    total_available: U256,
+    strategies: &[(SturdyDataParams, u64)],
+) -> AllocationInput {
+    AllocationInput {
+        chunk_count: 100,
+        total_initial_amount: U256::ZERO,
+        total_available_amount: total_available,
+        initial_datas: (0..strategies.len())
+            .map(|i| Position {
+                strategy: strategy(i),
+                debt: U256::ZERO,
+            })
+            .collect(),
+        strategy_datas: strategies
+            .iter()
+            .map(|(_, max_debt)| StrategyParams {
+                activation: U256::ZERO,
+                last_report: U256::ZERO,
+                current_debt: U256::ZERO,
+                max_debt: units(*max_debt),
+            })
+            .collect(),
+        sturdy_datas: strategies.iter().map(|(pair, _)| *pair).collect(),
+        objective: Objective::TotalInterest,
+        constraints: Constraints::default(),
+        cost_model: CostModel::default(),
+        vault: Address::with_last_byte(0xaa),
+        snapshot_timestamp: U256::from(SNAPSHOT_TIMESTAMP),
+        rate_models: vec![],
+        rate_horizon: U256::ZERO,
+    }
+}
+
+/// Two half-borrowed markets of 1000 units, the first paying a fixed rate
+/// twice that of the second, so its lenders earn as much as the second's
+/// once 1000 more units are deposited in it.
+pub(crate) fn two_markets(total_available: U256, max_debts: [u64; 2]) -> AllocationInput {
+    let mut input = vault(
+        total_available,
+        &[
+            (market(1000, 500, 2000), max_debts[0]),
+            (market(1000, 500, 1000), max_debts[1]),
+        ],
+    );
+    input.objective = Objective::MarginalRate;
+    input.rate_models = vec![fixed(2000), fixed(1000)];
+    input
+}
+
+/// The four-strategy vault of `ZKOptimalAllocation.t.sol`.
+pub(crate) fn mainnet_input(total_available: U256) -> AllocationInput {
+    let pairs: Vec<SturdyDataParams> = vec![
+        pair(
+            1694820803,
+            162996627,
+            1582470460,
+            1000515508599392284665549,
+            14607601723157079315388,
+        ),
+        pair(
+            1697698655,
+            918533958,
+            5894455579,
+            1007893653006093263158516,
+            646952490709056909560829,
+        ),
+        pair(
+            1697564051,
+            176565000,
+            1582470460,
+            1250761314812572567422124,
+            65381747702435868428080,
+        ),
+        pair(
+            1697096315,
+            334268038,
+            1582470460,
+            100187460695884368772125,
+            92172460683966387859446,
+        ),
+    ];
+    let strategies: Vec<_> = pairs.into_iter().zip([6000, 5000, 3000, 3000]).collect();
+    vault(total_available, &strategies)
+}
@@ -0,0 +1,57 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate models and optimal allocation algorithm shared by the
+//! `optimal_allocation` guest and host tooling.
+//!
+//! The crate is `no_std` so that it builds for the zkVM, and the host can
+//! compute the same allocation natively with [optimal_allocation], or with
+//! [optimal_allocations] for several vaults.
+#![no_std]
+
+extern crate alloc;
+
+pub mod abi;
+mod actions;
+mod constraints;
+mod cost;
+mod error;
+#[cfg(test)]
+mod fixtures;
+mod optimizer;
+pub mod rate_model;
+mod report;
+mod solver;
+mod sturdy;
+mod validation;
+mod vaults;
+
+pub use actions::{rebalance_actions, Direction, RebalanceAction};
+pub use constraints::Constraints;
+pub use cost::CostModel;
+pub use error::AllocationError;
+pub use optimizer::{
+    get_current_and_new_apr, get_current_and_new_interest, get_optimal_allocation,
+    get_unallocated_amount, optimal_allocation, AllocationInput, AllocationResult, Objective,
+    Position, StrategyParams,
+};
+pub use rate_model::{RateModel, RateModelKind, RateModelParams};
+pub use report::{strategy_reports, StrategyReport};
+pub use sturdy::{
+    apr_after_debt_change, average_rate_at_utilization, get_full_utilization_interest,
+    get_new_rate, rate_at_utilization, supply_rate, utilization_after_debt_change,
+    SturdyDataParams, APR_PRECISION, FEE_PRECISION, SECONDS_PER_YEAR,
+};
+pub use validation::validate_input;
+pub use vaults::optimal_allocations;
@@ -0,0 +1,500 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Optimal allocation of vault assets across Sturdy strategies.
+
+use alloc::{vec, vec::Vec};
+
+use alloy_primitives::{Address, B256, I256, U256};
+
+use crate::{
+    actions::{rebalance_actions, RebalanceAction},
+    constraints::{capacity, debt_bounds, Constraints},
+    cost::CostModel,
+    error::{add, div, mul, signed_sub, to_i256, to_u64, AllocationError},
+    rate_model::{apr_after_debt_change, build_rate_models, RateModelParams},
+    report::{strategy_reports, StrategyReport},
+    solver::water_fill,
+    sturdy::{SturdyDataParams, APR_PRECISION},
+    validation::validate_input,
+};
+
+/// Target debt of a strategy, mirroring `IDebtManager.StrategyAllocation`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub strategy: Address,
+    pub debt: U256,
+}
+
+/// Vault accounting of a strategy, mirroring `IVault.StrategyParams`.
+#[derive(Clone, Debug)]
+pub struct StrategyParams {
+    pub activation: U256,
+    pub last_report: U256,
+    pub current_debt: U256,
+    pub max_debt: U256,
+}
+
+/// Quantity the allocation maximizes, mirroring
+/// `ZKOptimalAllocation.Objective`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Objective {
+    /// Total interest earned across all strategies per year.
+    #[default]
+    TotalInterest = 0,
+    /// Equal marginal lender rate across strategies, ignoring the rate drop
+    /// on debt already deposited.
+    MarginalRate = 1,
+}
+
+impl TryFrom<u8> for Objective {
+    type Error = AllocationError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Objective::TotalInterest),
+            1 => Ok(Objective::MarginalRate),
+            _ => Err(AllocationError::InvalidInput),
+        }
+    }
+}
+
+/// Input of one vault of the `optimal_allocation` guest, in the order it is
+/// encoded by `ZKOptimalAllocation.optimalAllocationInput`.
+#[derive(Clone, Debug)]
+pub struct AllocationInput {
+    /// Maximum number of bisection steps spent on the solver level, raised to
+    /// at least 64, which is always enough for an exact level. Kept for
+    /// compatibility with callers of the chunked allocation; it must not be
+    /// zero.
+    pub chunk_count: u64,
+    pub total_initial_amount: U256,
+    pub total_available_amount: U256,
+    pub initial_datas: Vec<Position>,
+    pub strategy_datas: Vec<StrategyParams>,
+    pub sturdy_datas: Vec<SturdyDataParams>,
+    pub objective: Objective,
+    pub constraints: Constraints,
+    pub cost_model: CostModel,
+    /// Vault whose state the input was read from.
+    pub vault: Address,
+    /// Block timestamp at which the input was read.
+    pub snapshot_timestamp: U256,
+    /// Rate model of each strategy, in input order. Empty when every
+    /// strategy follows the Sturdy model.
+    pub rate_models: Vec<RateModelParams>,
+    /// Seconds the allocation is expected to hold. Sturdy rates are averaged
+    /// over this horizon as the full utilization rate drifts, instead of
+    /// taken at the snapshot. Zero optimizes the instantaneous rates.
+    pub rate_horizon: U256,
+}
+
+/// Result committed by the `optimal_allocation` guest for one vault.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllocationResult {
+    /// Target debts, withdrawals first. Empty unless `is_success`.
+    pub allocations: Vec<Position>,
+    /// Debt moves reaching `allocations`, in execution order. Empty unless
+    /// `is_success`.
+    pub actions: Vec<RebalanceAction>,
+    /// Figures of every strategy, in input order, the allocation was
+    /// computed from. Empty when the allocation could not be computed.
+    pub reports: Vec<StrategyReport>,
+    pub new_apr: u64,
+    pub current_apr: u64,
+    /// Interest earned per year by the new allocation, in asset units.
+    pub new_interest: U256,
+    /// Interest earned per year by the current debts, in asset units.
+    pub current_interest: U256,
+    /// Constraints the allocation satisfies.
+    pub constraints: Constraints,
+    /// Interest gained over the holding horizon minus the gas cost, in asset
+    /// base units. Zero when the cost model is disabled.
+    pub net_gain: I256,
+    /// Assets left idle, on top of the constraints' `min_idle`, because the
+    /// strategies cannot take them.
+    pub unallocated_amount: U256,
+    /// Whether the new allocation improves the objective.
+    pub is_success: bool,
+    /// [AllocationError] code, or zero when the allocation was computed.
+    pub error_code: u8,
+    /// Vault the input was read from. Zero when the input could not be
+    /// decoded.
+    pub vault: Address,
+    /// Keccak hash of the ABI encoded input, set by
+    /// [evaluate](crate::abi::evaluate) which sees the raw bytes.
+    pub input_hash: B256,
+    /// Block timestamp at which the input was read. Zero when the input
+    /// could not be decoded.
+    pub snapshot_timestamp: U256,
+}
+
+impl AllocationResult {
+    /// Result committed when the allocation could not be computed.
+    pub fn failure(error: AllocationError) -> Self {
+        Self {
+            allocations: vec![],
+            actions: vec![],
+            reports: vec![],
+            new_apr: 0,
+            current_apr: 0,
+            new_interest: U256::from(0),
+            current_interest: U256::from(0),
+            constraints: Constraints::default(),
+            net_gain: I256::ZERO,
+            unallocated_amount: U256::from(0),
+            is_success: false,
+            error_code: error.code(),
+            vault: Address::ZERO,
+            input_hash: B256::ZERO,
+            snapshot_timestamp: U256::from(0),
+        }
+    }
+}
+
+/// Compute the optimal allocation for `input` and whether it improves the
+/// chosen objective over the current one by enough to pay for the rebalance.
+pub fn optimal_allocation(input: &AllocationInput) -> Result<AllocationResult, AllocationError> {
+    let optimal_allocations = get_optimal_allocation(input)?;
+    let unallocated_amount = get_unallocated_amount(input)?;
+
+    let (current_apr, new_apr) = get_current_and_new_apr(
+        &input.initial_datas,
+        &input.sturdy_datas,
+        &input.rate_models,
+        input.rate_horizon,
+        &input.strategy_datas,
+        &optimal_allocations,
+    )?;
+
+    let (current_interest, new_interest) = get_current_and_new_interest(
+        &input.initial_datas,
+        &input.sturdy_datas,
+        &input.rate_models,
+        input.rate_horizon,
+        &input.strategy_datas,
+        &optimal_allocations,
+    )?;
+
+    let net_gain = if input.cost_model.is_enabled() {
+        input.cost_model.net_gain(current_interest, new_interest)?
+    } else {
+        I256::ZERO
+    };
+
+    let improves = match input.objective {
+        Objective::TotalInterest => new_interest > current_interest,
+        Objective::MarginalRate => new_apr > current_apr,
+    };
+    let is_success = improves && input.cost_model.beats_threshold(net_gain)?;
+    let reports = strategy_reports(input, &optimal_allocations)?;
+    let (allocations, actions) = if is_success {
+        let actions = rebalance_actions(input, &optimal_allocations)?;
+        (optimal_allocations, actions)
+    } else {
+        (vec![], vec![])
+    };
+    Ok(AllocationResult {
+        allocations,
+        actions,
+        reports,
+        new_apr,
+        current_apr,
+        new_interest,
+        current_interest,
+        constraints: input.constraints.clone(),
+        net_gain,
+        unallocated_amount,
+        is_success,
+        error_code: 0,
+        vault: input.vault,
+        input_hash: B256::ZERO,
+        snapshot_timestamp: input.snapshot_timestamp,
+    })
+}
+
+/// Split `total_available_amount - total_initial_amount` on top of the
+/// initial debts so that marginal values of the objective are equalized
+/// across strategies, within the bounds set by the constraints.
+///
+/// When the strategies cannot take it all, each one is filled up to its cap
+/// and the rest, [get_unallocated_amount], stays idle in the vault.
+///
+/// `chunk_count` bounds the number of bisection steps on the level, never below
+/// 64. Positions are returned withdrawals first. The input is checked by
+/// [validate_input] before anything is computed.
+pub fn get_optimal_allocation(input: &AllocationInput) -> Result<Vec<Position>, AllocationError> {
+    validate_input(input)?;
+    let initial_datas = &input.initial_datas;
+    let strategy_datas = &input.strategy_datas;
+    let strategy_count = initial_datas.len();
+
+    let (bounds, amount) = debt_bounds(input)?;
+    let unconstrained = bounds
+        .iter()
+        .zip(initial_datas)
+        .all(|(bounds, initial_data)| bounds.floor == initial_data.debt);
+    if amount == U256::from(0) && unconstrained {
+        return Ok(vec![]);
+    }
+
+    let rate_models =
+        build_rate_models(&input.sturdy_datas, &input.rate_models, input.rate_horizon)?;
+    let debts = water_fill(
+        input.objective,
+        amount.min(capacity(&bounds)?),
+        &bounds,
+        &input.sturdy_datas,
+        &rate_models,
+        strategy_datas,
+        input.chunk_count,
+    )?;
+
+    // Make position array - first withdraw positions and next deposit positions.
+    let mut deposits = Vec::new();
+    let mut withdraws = Vec::new();
+
+    for i in 0..strategy_count {
+        let position = Position {
+            strategy: initial_datas[i].strategy,
+            debt: debts[i],
+        };
+
+        if strategy_datas[i].current_debt > debts[i] {
+            withdraws.push(position);
+        } else {
+            deposits.push(position);
+        }
+    }
+
+    deposits.reverse();
+    withdraws.extend(deposits);
+
+    Ok(withdraws)
+}
+
+/// Part of the amount to distribute that no strategy can take without
+/// exceeding its cap, left idle by [get_optimal_allocation].
+pub fn get_unallocated_amount(input: &AllocationInput) -> Result<U256, AllocationError> {
+    validate_input(input)?;
+    let (bounds, amount) = debt_bounds(input)?;
+    Ok(amount.saturating_sub(capacity(&bounds)?))
+}
+
+pub fn get_current_and_new_apr(
+    initial_datas: &[Position],
+    sturdy_datas: &[SturdyDataParams],
+    rate_models: &[RateModelParams],
+    rate_horizon: U256,
+    strategy_datas: &[StrategyParams],
+    optimal_datas: &[Position],
+) -> Result<(u64, u64), AllocationError> {
+    let (current, new) = get_current_and_new_totals(
+        initial_datas,
+        sturdy_datas,
+        rate_models,
+        rate_horizon,
+        strategy_datas,
+        optimal_datas,
+    )?;
+    let weighted_apr = |(total_apr, total_amount): (U256, U256)| {
+        if total_apr == U256::from(0) || total_amount == U256::from(0) {
+            Ok(0)
+        } else {
+            to_u64(div(total_apr, total_amount)?)
+        }
+    };
+
+    Ok((weighted_apr(current)?, weighted_apr(new)?))
+}
+
+/// Interest earned per year, in asset units, by the current debts and by
+/// `optimal_datas`.
+pub fn get_current_and_new_interest(
+    initial_datas: &[Position],
+    sturdy_datas: &[SturdyDataParams],
+    rate_models: &[RateModelParams],
+    rate_horizon: U256,
+    strategy_datas: &[StrategyParams],
+    optimal_datas: &[Position],
+) -> Result<(U256, U256), AllocationError> {
+    let ((current, _), (new, _)) = get_current_and_new_totals(
+        initial_datas,
+        sturdy_datas,
+        rate_models,
+        rate_horizon,
+        strategy_datas,
+        optimal_datas,
+    )?;
+    let precision = U256::from(APR_PRECISION);
+
+    Ok((div(current, precision)?, div(new, precision)?))
+}
+
+/// Sum of `apr * debt` and sum of debt over a set of positions.
+type Totals = (U256, U256);
+
+/// [Totals] of the current debts and of `optimal_datas`.
+fn get_current_and_new_totals(
+    initial_datas: &[Position],
+    sturdy_datas: &[SturdyDataParams],
+    rate_models: &[RateModelParams],
+    rate_horizon: U256,
+    strategy_datas: &[StrategyParams],
+    optimal_datas: &[Position],
+) -> Result<(Totals, Totals), AllocationError> {
+    let strategy_count = initial_datas.len();
+    let mut total_amount = U256::from(0);
+    let mut total_apr = U256::from(0);
+    if optimal_datas.is_empty() {
+        return Ok(((total_apr, total_amount), (total_apr, total_amount)));
+    }
+    let rate_models = build_rate_models(sturdy_datas, rate_models, rate_horizon)?;
+
+    // get current apr
+    for ((sturdy_data, rate_model), strategy_data) in
+        sturdy_datas.iter().zip(&rate_models).zip(strategy_datas)
+    {
+        let apr = apr_after_debt_change(sturdy_data, rate_model.as_ref(), I256::ZERO)?;
+        total_apr = add(total_apr, mul(apr, strategy_data.current_debt)?)?;
+        total_amount = add(total_amount, strategy_data.current_debt)?;
+    }
+    let current = (total_apr, total_amount);
+
+    total_amount = U256::from(0);
+    total_apr = U256::from(0);
+    // get new apr
+    for optimal_data in optimal_datas.iter().take(strategy_count) {
+        let Some(index) = initial_datas
+            .iter()
+            .position(|initial_data| initial_data.strategy == optimal_data.strategy)
+        else {
+            break;
+        };
+
+        let apr = apr_after_debt_change(
+            &sturdy_datas[index],
+            rate_models[index].as_ref(),
+            signed_sub(
+                to_i256(optimal_data.debt)?,
+                to_i256(strategy_datas[index].current_debt)?,
+            )?,
+        )?;
+        total_apr = add(total_apr, mul(apr, optimal_data.debt)?)?;
+        total_amount = add(total_amount, optimal_data.debt)?;
+    }
+
+    Ok((current, (total_apr, total_amount)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::{Direction, RebalanceAction},
+        fixtures::{mainnet_input, strategy, two_markets, units},
+    };
+
+    fn total_debt(allocations: &[Position]) -> U256 {
+        allocations
+            .iter()
+            .fold(U256::ZERO, |total, position| total + position.debt)
+    }
+
+    #[test]
+    fn allocates_the_mainnet_vault() -> Result<(), AllocationError> {
+        let result = optimal_allocation(&mainnet_input(units(14000)))?;
+        assert!(result.is_success);
+        // Same expectation as `ZKOptimalAllocation.t.sol`.
+        assert_eq!(
+            result.allocations[0],
+            Position {
+                strategy: strategy(3),
+                debt: units(3000)
+            }
+        );
+        assert_eq!(total_debt(&result.allocations), units(14000));
+        assert_eq!(result.unallocated_amount, U256::ZERO);
+        assert!(result.new_interest > result.current_interest);
+        assert_eq!(result.reports.len(), 4);
+        assert!(result
+            .actions
+            .iter()
+            .all(|action| action.direction == Direction::Deposit));
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_what_the_caps_refuse_idle() -> Result<(), AllocationError> {
+        let input = two_markets(units(2000), [1000, 500]);
+        let result = optimal_allocation(&input)?;
+        assert!(result.is_success);
+        assert_eq!(result.unallocated_amount, units(500));
+        assert_eq!(get_unallocated_amount(&input)?, units(500));
+        assert_eq!(total_debt(&result.allocations), units(1500));
+        Ok(())
+    }
+
+    #[test]
+    fn moves_debt_to_the_better_paying_strategy() -> Result<(), AllocationError> {
+        // The second market holds the vault's 800 units on top of its 1000.
+        let mut input = two_markets(units(800), [2000, 2000]);
+        input.strategy_datas[1].current_debt = units(800);
+        input.sturdy_datas[1].total_asset = units(1800);
+        let result = optimal_allocation(&input)?;
+        assert!(result.is_success);
+        assert_eq!(
+            result.actions,
+            [
+                RebalanceAction {
+                    strategy: strategy(1),
+                    direction: Direction::Withdraw,
+                    amount: units(800),
+                },
+                RebalanceAction {
+                    strategy: strategy(0),
+                    direction: Direction::Deposit,
+                    amount: units(800),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fails_when_nothing_improves() -> Result<(), AllocationError> {
+        let result = optimal_allocation(&two_markets(U256::ZERO, [2000, 2000]))?;
+        assert!(!result.is_success);
+        assert!(result.allocations.is_empty() && result.actions.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn fails_below_the_min_net_gain() -> Result<(), AllocationError> {
+        let mut input = two_markets(units(800), [2000, 2000]);
+        input.cost_model = CostModel {
+            gas_cost: U256::ZERO,
+            asset_price: U256::ZERO,
+            holding_horizon: U256::from(86400),
+            min_net_gain: units(800),
+        };
+        let result = optimal_allocation(&input)?;
+        assert!(!result.is_success);
+        assert!(result.net_gain > I256::ZERO);
+        assert!(result.allocations.is_empty());
+        assert_eq!(result.reports.len(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interest rate models a strategy's pair can follow.
+//!
+//! The accounting of every pair comes in [SturdyDataParams]. Only the curve
+//! mapping utilization to a borrow rate differs, selected per strategy by a
+//! [RateModelKind] tag. The contract reads pairs of the other kinds through
+//! the generic `ILendingMarketStrategy` rather than the Sturdy pair and rate
+//! contracts, so their Sturdy curve constants are zero and never used.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use alloy_primitives::{I256, U256};
+
+use crate::{
+    error::{add, div, mul, sub, to_u64, AllocationError},
+    sturdy::{
+        average_rate_at_utilization, rate_at_utilization, supply_rate,
+        utilization_after_debt_change, SturdyDataParams,
+    },
+    SECONDS_PER_YEAR,
+};
+
+/// Curve mapping the utilization of a pair to its borrow rate.
+///
+/// Rates are per second in 1e18 precision and utilization is in the pair's
+/// `util_prec`. The rate must never decrease as utilization grows, which is
+/// what lets the solver search it by bisection.
+pub trait RateModel {
+    /// Borrow rate per second once the pair reaches `utilization`.
+    fn rate_at_utilization(&self, utilization: U256) -> Result<u64, AllocationError>;
+
+    /// Borrow rate per second the pair pays right now.
+    fn current_rate(&self) -> Result<u64, AllocationError>;
+
+    /// Whether the rate stays at [RateModel::current_rate] whatever the
+    /// utilization.
+    fn is_constant(&self) -> bool;
+}
+
+/// Tag selecting the rate model of a strategy, mirroring
+/// `ZKOptimalAllocation.RateModelKind`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RateModelKind {
+    /// Sturdy `VariableInterestRate`, configured by [SturdyDataParams].
+    #[default]
+    Sturdy = 0,
+    /// Aave and Compound style jump rate, configured by the base rate, the
+    /// two slopes and the kink of [RateModelParams].
+    LinearKink = 1,
+    /// Constant `base_rate`.
+    Fixed = 2,
+}
+
+impl TryFrom<u8> for RateModelKind {
+    type Error = AllocationError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(RateModelKind::Sturdy),
+            1 => Ok(RateModelKind::LinearKink),
+            2 => Ok(RateModelKind::Fixed),
+            _ => Err(AllocationError::InvalidInput),
+        }
+    }
+}
+
+/// Rate model of a strategy, mirroring `ZKOptimalAllocation.RateModelParams`.
+/// Fields a model does not use are ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateModelParams {
+    pub kind: RateModelKind,
+    /// Rate per second at zero utilization, or the rate of a fixed model.
+    pub base_rate: U256,
+    /// Rate per second added between zero utilization and the kink.
+    pub slope1: U256,
+    /// Rate per second added between the kink and full utilization.
+    pub slope2: U256,
+    /// Utilization, in `util_prec`, at which `slope2` takes over.
+    pub kink: U256,
+}
+
+impl RateModel for SturdyDataParams {
+    fn rate_at_utilization(&self, utilization: U256) -> Result<u64, AllocationError> {
+        rate_at_utilization(*self, utilization)
+    }
+
+    fn current_rate(&self) -> Result<u64, AllocationError> {
+        to_u64(self.rate_per_sec)
+    }
+
+    fn is_constant(&self) -> bool {
+        self.is_interest_paused
+    }
+}
+
+/// Sturdy model averaged over the `horizon` seconds the allocation is expected
+/// to hold, see [average_rate_at_utilization].
+#[derive(Clone, Copy, Debug)]
+pub struct ProjectedSturdyRate {
+    pub sturdy_data: SturdyDataParams,
+    /// Seconds over which the rate is averaged, never zero.
+    pub horizon: U256,
+}
+
+impl RateModel for ProjectedSturdyRate {
+    fn rate_at_utilization(&self, utilization: U256) -> Result<u64, AllocationError> {
+        average_rate_at_utilization(self.sturdy_data, utilization, self.horizon)
+    }
+
+    /// The rate keeps drifting when the debt does not move, so the current
+    /// rate is the average one at the current utilization rather than the
+    /// rate stored by the pair.
+    fn current_rate(&self) -> Result<u64, AllocationError> {
+        if self.is_constant() {
+            return to_u64(self.sturdy_data.rate_per_sec);
+        }
+        let utilization = utilization_after_debt_change(self.sturdy_data, I256::ZERO)?;
+        self.rate_at_utilization(utilization)
+    }
+
+    fn is_constant(&self) -> bool {
+        self.sturdy_data.is_interest_paused
+    }
+}
+
+/// Jump rate model: `base_rate + slope1 * utilization / kink` up to the kink,
+/// then `slope2` spread over the remaining utilization.
+#[derive(Clone, Copy, Debug)]
+pub struct LinearKinkRate {
+    pub base_rate: U256,
+    pub slope1: U256,
+    pub slope2: U256,
+    pub kink: U256,
+    pub util_prec: U256,
+    /// Rate per second stored by the pair.
+    pub rate_per_sec: U256,
+    pub is_interest_paused: bool,
+}
+
+impl RateModel for LinearKinkRate {
+    fn rate_at_utilization(&self, utilization: U256) -> Result<u64, AllocationError> {
+        let below_kink = utilization.min(self.kink);
+        let mut rate = add(
+            self.base_rate,
+            div(mul(self.slope1, below_kink)?, self.kink)?,
+        )?;
+        if utilization > self.kink {
+            let above_kink = sub(utilization, self.kink)?;
+            rate = add(
+                rate,
+                div(
+                    mul(self.slope2, above_kink)?,
+                    sub(self.util_prec, self.kink)?,
+                )?,
+            )?;
+        }
+        to_u64(rate)
+    }
+
+    fn current_rate(&self) -> Result<u64, AllocationError> {
+        to_u64(self.rate_per_sec)
+    }
+
+    fn is_constant(&self) -> bool {
+        self.is_interest_paused
+    }
+}
+
+/// Rate that does not depend on utilization.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedRate {
+    pub rate_per_sec: U256,
+}
+
+impl RateModel for FixedRate {
+    fn rate_at_utilization(&self, _utilization: U256) -> Result<u64, AllocationError> {
+        self.current_rate()
+    }
+
+    fn current_rate(&self) -> Result<u64, AllocationError> {
+        to_u64(self.rate_per_sec)
+    }
+
+    fn is_constant(&self) -> bool {
+        true
+    }
+}
+
+/// Build the rate model `params` describes for the pair `sturdy_data`, with
+/// Sturdy rates averaged over `horizon` seconds when it is not zero. The other
+/// models do not drift over time.
+pub fn rate_model(
+    sturdy_data: &SturdyDataParams,
+    params: &RateModelParams,
+    horizon: U256,
+) -> Result<Box<dyn RateModel>, AllocationError> {
+    Ok(match params.kind {
+        RateModelKind::Sturdy if horizon == U256::from(0) => Box::new(*sturdy_data),
+        RateModelKind::Sturdy => Box::new(ProjectedSturdyRate {
+            sturdy_data: *sturdy_data,
+            horizon,
+        }),
+        RateModelKind::LinearKink => {
+            if params.kink == U256::from(0) || params.kink > sturdy_data.util_prec {
+                return Err(AllocationError::InvalidInput);
+            }
+            Box::new(LinearKinkRate {
+                base_rate: params.base_rate,
+                slope1: params.slope1,
+                slope2: params.slope2,
+                kink: params.kink,
+                util_prec: sturdy_data.util_prec,
+                rate_per_sec: sturdy_data.rate_per_sec,
+                is_interest_paused: sturdy_data.is_interest_paused,
+            })
+        }
+        RateModelKind::Fixed => Box::new(FixedRate {
+            rate_per_sec: params.base_rate,
+        }),
+    })
+}
+
+/// Rate model of every strategy, see [rate_model]. An empty `params` selects
+/// the Sturdy model for all of them.
+pub(crate) fn build_rate_models(
+    sturdy_datas: &[SturdyDataParams],
+    params: &[RateModelParams],
+    horizon: U256,
+) -> Result<Vec<Box<dyn RateModel>>, AllocationError> {
+    if params.is_empty() {
+        return sturdy_datas
+            .iter()
+            .map(|sturdy_data| rate_model(sturdy_data, &RateModelParams::default(), horizon))
+            .collect();
+    }
+    if params.len() != sturdy_datas.len() {
+        return Err(AllocationError::InvalidInput);
+    }
+    sturdy_datas
+        .iter()
+        .zip(params)
+        .map(|(sturdy_data, params)| rate_model(sturdy_data, params, horizon))
+        .collect()
+}
+
+/// Borrow rate per second of a pair following `model` once `delta` is added
+/// to, or removed from, its assets.
+pub fn rate_after_debt_change(
+    sturdy_data: &SturdyDataParams,
+    model: &dyn RateModel,
+    delta: I256,
+) -> Result<u64, AllocationError> {
+    if delta == I256::ZERO || model.is_constant() {
+        model.current_rate()
+    } else {
+        model.rate_at_utilization(utilization_after_debt_change(*sturdy_data, delta)?)
+    }
+}
+
+/// Lender APR, net of the protocol fee, of a pair following `model` once
+/// `delta` is added to, or removed from, its assets. See [supply_rate].
+pub fn apr_after_debt_change(
+    sturdy_data: &SturdyDataParams,
+    model: &dyn RateModel,
+    delta: I256,
+) -> Result<U256, AllocationError> {
+    let rate_per_sec = rate_after_debt_change(sturdy_data, model, delta)?;
+    let utilization = utilization_after_debt_change(*sturdy_data, delta)?;
+    let supply_rate_per_sec = supply_rate(rate_per_sec, utilization, sturdy_data)?;
+    mul(
+        U256::from(supply_rate_per_sec),
+        U256::from(SECONDS_PER_YEAR),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{market, units};
+
+    fn params(kind: RateModelKind, kink: u64) -> RateModelParams {
+        RateModelParams {
+            kind,
+            base_rate: U256::from(100),
+            slope1: U256::from(1000),
+            slope2: U256::from(10000),
+            kink: U256::from(kink),
+        }
+    }
+
+    #[test]
+    fn linear_kink_steepens_past_the_kink() -> Result<(), AllocationError> {
+        let sturdy_data = market(1000, 500, 162996627);
+        let model = rate_model(
+            &sturdy_data,
+            &params(RateModelKind::LinearKink, 80000),
+            U256::ZERO,
+        )?;
+        for (utilization, rate) in [(0, 100), (40000, 600), (80000, 1100), (90000, 6100)] {
+            assert_eq!(model.rate_at_utilization(U256::from(utilization))?, rate);
+        }
+        assert_eq!(model.current_rate()?, 162996627);
+        for kink in [0, 100001] {
+            assert!(matches!(
+                rate_model(
+                    &sturdy_data,
+                    &params(RateModelKind::LinearKink, kink),
+                    U256::ZERO
+                ),
+                Err(AllocationError::InvalidInput)
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_rate_ignores_debt_changes() -> Result<(), AllocationError> {
+        let sturdy_data = market(1000, 500, 162996627);
+        let model = rate_model(&sturdy_data, &params(RateModelKind::Fixed, 0), U256::ZERO)?;
+        assert!(model.is_constant());
+        let delta = I256::from_raw(units(1000));
+        assert_eq!(
+            rate_after_debt_change(&sturdy_data, model.as_ref(), delta)?,
+            100
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sturdy_rate_follows_utilization() -> Result<(), AllocationError> {
+        let sturdy_data = market(1000, 600, 918533958);
+        let models = build_rate_models(&[sturdy_data], &[], U256::ZERO)?;
+        let model = models[0].as_ref();
+        assert_eq!(
+            rate_after_debt_change(&sturdy_data, model, I256::ZERO)?,
+            918533958
+        );
+        let delta = I256::from_raw(units(100000));
+        let utilization = utilization_after_debt_change(sturdy_data, delta)?;
+        assert_eq!(
+            rate_after_debt_change(&sturdy_data, model, delta)?,
+            rate_at_utilization(sturdy_data, utilization)?
+        );
+        assert!(
+            apr_after_debt_change(&sturdy_data, model, delta)?
+                < apr_after_debt_change(&sturdy_data, model, I256::ZERO)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn projected_sturdy_rate_drifts_unless_paused() -> Result<(), AllocationError> {
+        // The pair is above its target utilization, so its rate rises.
+        let mut sturdy_data = market(1000, 920, 334268038);
+        let horizon = U256::from(86400);
+        let model = rate_model(&sturdy_data, &RateModelParams::default(), horizon)?;
+        assert!(
+            model.current_rate()?
+                > rate_at_utilization(
+                    sturdy_data,
+                    utilization_after_debt_change(sturdy_data, I256::ZERO)?
+                )?
+        );
+
+        sturdy_data.is_interest_paused = true;
+        let model = rate_model(&sturdy_data, &RateModelParams::default(), horizon)?;
+        assert!(model.is_constant());
+        assert_eq!(model.current_rate()?, 334268038);
+        Ok(())
+    }
+
+    #[test]
+    fn requires_one_model_per_strategy() {
+        let sturdy_datas = [market(1000, 500, 0); 2];
+        let params = [RateModelParams::default(); 3];
+        assert!(matches!(
+            build_rate_models(&sturdy_datas, &params, U256::ZERO),
+            Err(AllocationError::InvalidInput)
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-strategy figures an allocation was computed from.
+
+use alloc::vec::Vec;
+
+use alloy_primitives::{Address, I256, U256};
+
+use crate::{
+    error::{signed_sub, to_i256, AllocationError},
+    optimizer::{AllocationInput, Position},
+    rate_model::{apr_after_debt_change, build_rate_models, rate_after_debt_change},
+    sturdy::utilization_after_debt_change,
+};
+
+/// Current and projected state of a strategy's pair, mirroring
+/// `ZKOptimalAllocation.StrategyReport`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StrategyReport {
+    pub strategy: Address,
+    pub current_debt: U256,
+    /// Debt proposed by the allocation, the current debt when the strategy is
+    /// left out of it.
+    pub target_debt: U256,
+    /// Precision of the utilizations.
+    pub util_prec: U256,
+    pub current_utilization: U256,
+    pub projected_utilization: U256,
+    /// Borrow rates per second, in 1e18 precision.
+    pub current_rate_per_sec: u64,
+    pub projected_rate_per_sec: u64,
+    /// Lender APRs, net of the protocol fee, in 1e18 precision.
+    pub current_apr: U256,
+    pub projected_apr: U256,
+}
+
+/// Report of every strategy of `input`, in input order, once its debt moves
+/// from its current value to its debt in `allocations`.
+pub fn strategy_reports(
+    input: &AllocationInput,
+    allocations: &[Position],
+) -> Result<Vec<StrategyReport>, AllocationError> {
+    let rate_models =
+        build_rate_models(&input.sturdy_datas, &input.rate_models, input.rate_horizon)?;
+
+    let mut reports = Vec::with_capacity(input.initial_datas.len());
+    for (((initial_data, strategy_data), sturdy_data), rate_model) in input
+        .initial_datas
+        .iter()
+        .zip(&input.strategy_datas)
+        .zip(&input.sturdy_datas)
+        .zip(&rate_models)
+    {
+        let current_debt = strategy_data.current_debt;
+        let target_debt = allocations
+            .iter()
+            .find(|position| position.strategy == initial_data.strategy)
+            .map_or(current_debt, |position| position.debt);
+        let delta = signed_sub(to_i256(target_debt)?, to_i256(current_debt)?)?;
+        let model = rate_model.as_ref();
+
+        reports.push(StrategyReport {
+            strategy: initial_data.strategy,
+            current_debt,
+            target_debt,
+            util_prec: sturdy_data.util_prec,
+            current_utilization: utilization_after_debt_change(*sturdy_data, I256::ZERO)?,
+            projected_utilization: utilization_after_debt_change(*sturdy_data, delta)?,
+            current_rate_per_sec: rate_after_debt_change(sturdy_data, model, I256::ZERO)?,
+            projected_rate_per_sec: rate_after_debt_change(sturdy_data, model, delta)?,
+            current_apr: apr_after_debt_change(sturdy_data, model, I256::ZERO)?,
+            projected_apr: apr_after_debt_change(sturdy_data, model, delta)?,
+        });
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        fixtures::{fixed, market, strategy, units, vault},
+        sturdy::SECONDS_PER_YEAR,
+    };
+
+    /// Two half-borrowed markets of 1000 units at a fixed rate of 1000 per
+    /// second, the first holding 100 units of the vault.
+    fn input() -> AllocationInput {
+        let mut input = vault(units(2000), &[(market(1000, 500, 1000), 2000); 2]);
+        input.strategy_datas[0].current_debt = units(100);
+        input.rate_models = vec![fixed(1000); 2];
+        input
+    }
+
+    fn apr(supply_rate_per_sec: u64) -> U256 {
+        U256::from(supply_rate_per_sec) * U256::from(SECONDS_PER_YEAR)
+    }
+
+    #[test]
+    fn projects_allocated_debts() -> Result<(), AllocationError> {
+        let allocations = [Position {
+            strategy: strategy(1),
+            debt: units(250),
+        }];
+        let reports = strategy_reports(&input(), &allocations)?;
+        assert_eq!(
+            reports[1],
+            StrategyReport {
+                strategy: strategy(1),
+                current_debt: U256::ZERO,
+                target_debt: units(250),
+                util_prec: U256::from(100000),
+                current_utilization: U256::from(50000),
+                projected_utilization: U256::from(40000),
+                current_rate_per_sec: 1000,
+                projected_rate_per_sec: 1000,
+                current_apr: apr(500),
+                projected_apr: apr(400),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_strategies_left_out_at_their_current_debt() -> Result<(), AllocationError> {
+        let reports = strategy_reports(&input(), &[])?;
+        assert_eq!(reports.len(), 2);
+        let kept = &reports[0];
+        assert_eq!(
+            (kept.current_debt, kept.target_debt),
+            (units(100), units(100))
+        );
+        assert_eq!(kept.projected_utilization, kept.current_utilization);
+        assert_eq!(kept.projected_apr, kept.current_apr);
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Water-filling solver equalizing marginal values across strategies.
+//!
+//! The lender rate of a pair only depends on its utilization, which is an
+//! integer in `[0, util_prec]` that falls as debt is added. For a level `L`, each
+//! strategy takes the largest debt whose marginal value, as defined by the
+//! [Objective], still reaches `L`, found by bisection on utilization. The
+//! level itself is found by bisection on the total debt it absorbs, so the
+//! cost grows with the logarithm of the value and utilization ranges instead
+//! of with the number of chunks.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use alloy_primitives::{I256, U256};
+
+use crate::{
+    constraints::{capacity, DebtBounds},
+    error::{
+        add, div, mul, signed_add, signed_div, signed_mul, signed_sub, sub, to_i256, to_u256,
+        AllocationError,
+    },
+    optimizer::{Objective, StrategyParams},
+    rate_model::RateModel,
+    sturdy::{supply_rate, utilization_after_debt_change, SturdyDataParams},
+};
+
+/// Bisection steps always allowed on the level.
+///
+/// Under [Objective::MarginalRate] marginal values are lender rates per
+/// second, which fit in a `u64`, so this many halvings make the level exact
+/// whatever the `chunk_count` of the input. Under [Objective::TotalInterest]
+/// they are slopes of `debt * rate` over a single utilization step, which
+/// are not bounded by a `u64` rate, so the level may still be approximate
+/// after this many steps. The debt left over is then handed out as usual.
+pub(crate) const MIN_SOLVER_STEPS: u64 = 64;
+
+/// Debt range and rate curve of a single strategy.
+struct Curve<'a> {
+    objective: Objective,
+    sturdy_data: SturdyDataParams,
+    rate_model: &'a dyn RateModel,
+    current_debt: U256,
+    /// Debt the strategy keeps whatever the level.
+    floor: U256,
+    /// Debt the strategy never exceeds.
+    cap: U256,
+    /// Utilization reached at `cap`.
+    min_utilization: U256,
+    /// Utilization reached at `floor`.
+    max_utilization: U256,
+}
+
+impl<'a> Curve<'a> {
+    fn new(
+        objective: Objective,
+        sturdy_data: SturdyDataParams,
+        rate_model: &'a dyn RateModel,
+        strategy_data: &StrategyParams,
+        DebtBounds { floor, cap }: DebtBounds,
+    ) -> Result<Self, AllocationError> {
+        // Lenders share the interest, so their rate moves with utilization
+        // even when the borrow rate is frozen.
+        let current_debt = to_i256(strategy_data.current_debt)?;
+        let min_utilization =
+            utilization_after_debt_change(sturdy_data, signed_sub(to_i256(cap)?, current_debt)?)?;
+        let max_utilization =
+            utilization_after_debt_change(sturdy_data, signed_sub(to_i256(floor)?, current_debt)?)?;
+
+        Ok(Self {
+            objective,
+            sturdy_data,
+            rate_model,
+            current_debt: strategy_data.current_debt,
+            floor,
+            cap,
+            min_utilization,
+            max_utilization,
+        })
+    }
+
+    /// Lender rate per second at `utilization`, see [supply_rate].
+    fn rate(&self, utilization: U256) -> Result<u64, AllocationError> {
+        let borrow_rate = if self.rate_model.is_constant() {
+            self.rate_model.current_rate()?
+        } else {
+            self.rate_model.rate_at_utilization(utilization)?
+        };
+        supply_rate(borrow_rate, utilization, &self.sturdy_data)
+    }
+
+    /// Marginal value of the debt taken between `utilization + 1` and
+    /// `utilization`.
+    ///
+    /// For [Objective::TotalInterest] this is the slope of `debt * rate` over
+    /// that step, which accounts for the lower rate paid on the debt already
+    /// in the strategy.
+    fn marginal(&self, utilization: U256) -> Result<I256, AllocationError> {
+        let rate = to_i256(U256::from(self.rate(utilization)?))?;
+        if self.objective == Objective::MarginalRate {
+            return Ok(rate);
+        }
+
+        let next = add(utilization, U256::from(1))?;
+        let debt = self.debt_at_utilization(utilization)?;
+        let next_debt = self.debt_at_utilization(next)?;
+        if debt == next_debt {
+            return Ok(rate);
+        }
+        let next_rate = to_i256(U256::from(self.rate(next)?))?;
+        let (debt, next_debt) = (to_i256(debt)?, to_i256(next_debt)?);
+        signed_div(
+            signed_sub(signed_mul(debt, rate)?, signed_mul(next_debt, next_rate)?)?,
+            signed_sub(debt, next_debt)?,
+        )
+    }
+
+    /// Smallest utilization in `[low, high)` whose marginal value reaches
+    /// `level`, or `high` when there is none.
+    fn utilization_at_level(
+        &self,
+        level: I256,
+        mut low: U256,
+        mut high: U256,
+    ) -> Result<U256, AllocationError> {
+        while low < high {
+            let mid = div(add(low, high)?, U256::from(2))?;
+            if self.marginal(mid)? >= level {
+                high = mid;
+            } else {
+                low = add(mid, U256::from(1))?;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Largest debt keeping the utilization at or above `utilization`.
+    fn debt_at_utilization(&self, utilization: U256) -> Result<U256, AllocationError> {
+        if utilization > self.max_utilization {
+            return Ok(self.floor);
+        }
+        if utilization <= self.min_utilization {
+            return Ok(self.cap);
+        }
+
+        let max_assets = div(
+            mul(self.sturdy_data.util_prec, self.sturdy_data.total_borrow)?,
+            utilization,
+        )?;
+        let debt = to_u256(signed_sub(
+            signed_add(to_i256(max_assets)?, to_i256(self.current_debt)?)?,
+            to_i256(self.sturdy_data.total_asset)?,
+        )?)?;
+        Ok(debt.clamp(self.floor, self.cap))
+    }
+}
+
+/// Distribute `amount` on top of the debt floors so that the marginal values of the
+/// strategies taking debt are as equal as possible.
+///
+/// At most `max_steps`, and never fewer than [MIN_SOLVER_STEPS], bisection
+/// steps are spent on the level, stopping earlier once it is exact. Debt left
+/// over at the final level is handed out in input order: among strategies
+/// with equal marginal values, the first one listed is filled up to that level
+/// before the next one gets any, so ties always resolve the same way. Returns
+/// the target debt of each strategy.
+///
+/// `amount` must fit within the bounds, see
+/// [get_optimal_allocation](crate::get_optimal_allocation) for how the
+/// optimizer leaves the excess idle.
+///
+/// The result is optimal when marginal values fall as debt grows, which always
+/// holds for [Objective::MarginalRate].
+pub(crate) fn water_fill(
+    objective: Objective,
+    amount: U256,
+    bounds: &[DebtBounds],
+    sturdy_datas: &[SturdyDataParams],
+    rate_models: &[Box<dyn RateModel>],
+    strategy_datas: &[StrategyParams],
+    max_steps: u64,
+) -> Result<Vec<U256>, AllocationError> {
+    let curves = bounds
+        .iter()
+        .zip(sturdy_datas)
+        .zip(rate_models)
+        .zip(strategy_datas)
+        .map(|(((bounds, sturdy_data), rate_model), strategy_data)| {
+            Curve::new(
+                objective,
+                *sturdy_data,
+                rate_model.as_ref(),
+                strategy_data,
+                *bounds,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if capacity(bounds)? < amount {
+        return Err(AllocationError::NoAvailableStrategy);
+    }
+
+    // Every strategy reaches `low` at its cap and none reaches `high`, so the
+    // level absorbing `amount` lies in between.
+    let mut low = I256::MAX;
+    let mut high = I256::MIN;
+    for curve in &curves {
+        low = low.min(curve.marginal(curve.min_utilization)?);
+        high = high.max(signed_add(
+            curve.marginal(curve.max_utilization)?,
+            I256::ONE,
+        )?);
+    }
+    let mut low_utilizations: Vec<U256> =
+        curves.iter().map(|curve| curve.min_utilization).collect();
+    let mut high_utilizations: Vec<U256> = curves
+        .iter()
+        .map(|curve| add(curve.max_utilization, U256::from(1)))
+        .collect::<Result<_, _>>()?;
+
+    let max_steps = max_steps.max(MIN_SOLVER_STEPS);
+    let mut steps = 0;
+    while steps < max_steps && signed_sub(high, low)? > I256::ONE {
+        steps += 1;
+        let level = signed_add(
+            low,
+            signed_div(signed_sub(high, low)?, I256::from_raw(U256::from(2)))?,
+        )?;
+
+        let mut utilizations = Vec::with_capacity(curves.len());
+        let mut absorbed = U256::from(0);
+        for ((curve, low_utilization), high_utilization) in
+            curves.iter().zip(&low_utilizations).zip(&high_utilizations)
+        {
+            let utilization =
+                curve.utilization_at_level(level, *low_utilization, *high_utilization)?;
+            absorbed = add(
+                absorbed,
+                sub(curve.debt_at_utilization(utilization)?, curve.floor)?,
+            )?;
+            utilizations.push(utilization);
+        }
+
+        if absorbed >= amount {
+            low = level;
+            low_utilizations = utilizations;
+        } else {
+            high = level;
+            high_utilizations = utilizations;
+        }
+    }
+
+    // Fill every strategy up to the `high` level, then spread what is left
+    // over the strategies whose debt grows between `high` and `low`.
+    let mut debts = curves
+        .iter()
+        .zip(&high_utilizations)
+        .map(|(curve, utilization)| curve.debt_at_utilization(*utilization))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut filled = U256::from(0);
+    for (debt, curve) in debts.iter().zip(&curves) {
+        filled = add(filled, sub(*debt, curve.floor)?)?;
+    }
+    let mut remaining = sub(amount, filled)?;
+    for ((debt, curve), utilization) in debts.iter_mut().zip(&curves).zip(&low_utilizations) {
+        let extra = sub(curve.debt_at_utilization(*utilization)?, *debt)?.min(remaining);
+        *debt = add(*debt, extra)?;
+        remaining = sub(remaining, extra)?;
+    }
+
+    Ok(debts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{mainnet_input, two_markets, units},
+        optimizer::AllocationInput,
+        rate_model::build_rate_models,
+    };
+
+    fn fill(
+        input: &AllocationInput,
+        bounds: &[DebtBounds],
+        amount: U256,
+        max_steps: u64,
+    ) -> Result<Vec<U256>, AllocationError> {
+        let rate_models = build_rate_models(&input.sturdy_datas, &input.rate_models, U256::ZERO)?;
+        water_fill(
+            input.objective,
+            amount,
+            bounds,
+            &input.sturdy_datas,
+            &rate_models,
+            &input.strategy_datas,
+            max_steps,
+        )
+    }
+
+    fn bounds<const N: usize>(floors: [u64; N], caps: [u64; N]) -> Vec<DebtBounds> {
+        floors
+            .into_iter()
+            .zip(caps)
+            .map(|(floor, cap)| DebtBounds {
+                floor: units(floor),
+                cap: units(cap),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fills_the_best_paying_strategy_first() -> Result<(), AllocationError> {
+        // The first market pays more until 1000 units are deposited in it.
+        let input = two_markets(U256::ZERO, [2000, 2000]);
+        let debts = fill(&input, &bounds([0, 0], [2000, 2000]), units(800), 100)?;
+        assert_eq!(debts, [units(800), U256::ZERO]);
+        Ok(())
+    }
+
+    #[test]
+    fn equalizes_lender_rates() -> Result<(), AllocationError> {
+        // 2000 * 500 / (1000 + 1400) = 1000 * 500 / (1000 + 200).
+        let input = two_markets(U256::ZERO, [2000, 2000]);
+        let debts = fill(&input, &bounds([0, 0], [2000, 2000]), units(1600), 100)?;
+        assert_eq!(debts[0] + debts[1], units(1600));
+        for (debt, expected) in debts.iter().zip([units(1400), units(200)]) {
+            assert!(debt.abs_diff(expected) < units(1), "{debt} != {expected}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn spills_over_caps_and_keeps_floors() -> Result<(), AllocationError> {
+        let input = two_markets(U256::ZERO, [2000, 2000]);
+        let debts = fill(&input, &bounds([0, 0], [500, 2000]), units(800), 100)?;
+        assert_eq!(debts, [units(500), units(300)]);
+
+        // 300 units of floor lower the second market's rate further.
+        let debts = fill(&input, &bounds([0, 300], [2000, 2000]), units(800), 100)?;
+        assert_eq!(debts, [units(800), units(300)]);
+        Ok(())
+    }
+
+    #[test]
+    fn breaks_ties_in_input_order() -> Result<(), AllocationError> {
+        let mut input = two_markets(U256::ZERO, [2000, 2000]);
+        input.sturdy_datas[0] = input.sturdy_datas[1];
+        input.rate_models[0] = input.rate_models[1];
+        let debts = fill(&input, &bounds([0, 0], [2000, 2000]), U256::from(1), 100)?;
+        assert_eq!(debts, [U256::from(1), U256::ZERO]);
+        Ok(())
+    }
+
+    #[test]
+    fn fails_beyond_the_capacity() -> Result<(), AllocationError> {
+        let input = two_markets(U256::ZERO, [2000, 2000]);
+        let bounds = bounds([100, 0], [500, 700]);
+        assert_eq!(
+            fill(&input, &bounds, units(1100), 100)?,
+            [units(500), units(700)]
+        );
+        assert_eq!(
+            fill(&input, &bounds, units(1100) + U256::from(1), 100),
+            Err(AllocationError::NoAvailableStrategy)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn takes_at_least_the_minimum_steps() -> Result<(), AllocationError> {
+        let input = mainnet_input(U256::ZERO);
+        let bounds = bounds([0; 4], [6000, 5000, 3000, 3000]);
+        assert_eq!(
+            fill(&input, &bounds, units(14000), 1)?,
+            fill(&input, &bounds, units(14000), MIN_SOLVER_STEPS)?
+        );
+        Ok(())
+    }
+}
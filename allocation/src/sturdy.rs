@@ -0,0 +1,421 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sturdy variable interest rate model.
+
+use alloy_primitives::{I256, U256};
+
+use crate::{
+    error::{add, div, mul, sub, to_i256, to_u256, to_u64, AllocationError},
+    rate_model,
+};
+
+/// Rate state and curve constants of a Sturdy pair, as read by
+/// `ZKOptimalAllocation`.
+#[derive(Clone, Copy, Debug)]
+pub struct SturdyDataParams {
+    pub cur_timestamp: U256,
+    pub last_timestamp: U256,
+    pub rate_per_sec: U256,
+    pub full_utilization_rate: U256,
+    pub total_asset: U256,
+    pub total_borrow: U256,
+    pub util_prec: U256,
+    pub min_target_util: U256,
+    pub max_target_util: U256,
+    pub vertex_utilization: U256,
+    pub min_full_util_rate: U256,
+    pub max_full_util_rate: U256,
+    pub zero_util_rate: U256,
+    pub rate_half_life: U256,
+    pub vertex_rate_percent: U256,
+    pub rate_prec: U256,
+    pub is_interest_paused: bool,
+    /// Share of the interest taken by the protocol, in [FEE_PRECISION].
+    pub fee_to_protocol_rate: U256,
+}
+
+pub const SECONDS_PER_YEAR: u128 = 31556952;
+
+/// Precision of rates per second and APRs.
+pub const APR_PRECISION: u64 = 1_000_000_000_000_000_000;
+
+/// Precision of the pair's `feeToProtocolRate`, the `FEE_PRECISION` of
+/// `SturdyPairConstants`.
+pub const FEE_PRECISION: u64 = 100_000;
+
+/// Intervals of the trapezoidal rule averaging the rate over a horizon.
+const HORIZON_INTERVALS: u64 = 8;
+
+/// Scale of the utilization distance to the target range, the `1e18` of
+/// `VariableInterestRate`.
+const DELTA_UTILIZATION_PRECISION: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]);
+
+/// Scale of the rate half life in the decay growth, the `1e36` of
+/// `VariableInterestRate`.
+const DECAY_GROWTH_PRECISION: U256 = U256::from_limbs([
+    DECAY_GROWTH_PRECISION_U128 as u64,
+    (DECAY_GROWTH_PRECISION_U128 >> 64) as u64,
+    0,
+    0,
+]);
+const DECAY_GROWTH_PRECISION_U128: u128 = 1_000_000_000_000_000_000_000_000_000_000_000_000;
+
+/// Full utilization rate after `delta_time` seconds at `utilization`, as
+/// computed by `VariableInterestRate.getFullUtilizationInterest`.
+///
+/// Solidity truncates the result to `uint64` before clamping it; overflowing
+/// values are reported as [`AllocationError::Overflow`] instead.
+pub fn get_full_utilization_interest(
+    delta_time: U256,
+    utilization: U256,
+    sturdy_data: SturdyDataParams,
+) -> Result<u64, AllocationError> {
+    let mut new_full_utilization_interest: u64;
+
+    if utilization < sturdy_data.min_target_util {
+        let delta_utilization = div(
+            mul(
+                sub(sturdy_data.min_target_util, utilization)?,
+                DELTA_UTILIZATION_PRECISION,
+            )?,
+            sturdy_data.min_target_util,
+        )?;
+        let half_life = mul(sturdy_data.rate_half_life, DECAY_GROWTH_PRECISION)?;
+        let decay_growth = add(
+            half_life,
+            mul(mul(delta_utilization, delta_utilization)?, delta_time)?,
+        )?;
+        new_full_utilization_interest = to_u64(div(
+            mul(sturdy_data.full_utilization_rate, half_life)?,
+            decay_growth,
+        )?)?;
+    } else if utilization > sturdy_data.max_target_util {
+        let delta_utilization = div(
+            mul(
+                sub(utilization, sturdy_data.max_target_util)?,
+                DELTA_UTILIZATION_PRECISION,
+            )?,
+            sub(sturdy_data.util_prec, sturdy_data.max_target_util)?,
+        )?;
+        let half_life = mul(sturdy_data.rate_half_life, DECAY_GROWTH_PRECISION)?;
+        let decay_growth = add(
+            half_life,
+            mul(mul(delta_utilization, delta_utilization)?, delta_time)?,
+        )?;
+        new_full_utilization_interest = to_u64(div(
+            mul(sturdy_data.full_utilization_rate, decay_growth)?,
+            half_life,
+        )?)?;
+    } else {
+        new_full_utilization_interest = to_u64(sturdy_data.full_utilization_rate)?;
+    }
+
+    let max_full_util_rate = to_u64(sturdy_data.max_full_util_rate)?;
+    let min_full_util_rate = to_u64(sturdy_data.min_full_util_rate)?;
+    if new_full_utilization_interest > max_full_util_rate {
+        new_full_utilization_interest = max_full_util_rate;
+    } else if new_full_utilization_interest < min_full_util_rate {
+        new_full_utilization_interest = min_full_util_rate;
+    }
+
+    Ok(new_full_utilization_interest)
+}
+
+/// Rate per second and full utilization rate after `delta_time` seconds at
+/// `utilization`, as computed by `VariableInterestRate.getNewRate`.
+pub fn get_new_rate(
+    delta_time: U256,
+    utilization: U256,
+    sturdy_data: SturdyDataParams,
+) -> Result<(u64, u64), AllocationError> {
+    let new_full_utilization_interest =
+        get_full_utilization_interest(delta_time, utilization, sturdy_data)?;
+    let full_utilization_interest = U256::from(new_full_utilization_interest);
+    let zero_util_rate = sturdy_data.zero_util_rate;
+
+    let vertex_interest = add(
+        div(
+            mul(
+                sub(full_utilization_interest, zero_util_rate)?,
+                sturdy_data.vertex_rate_percent,
+            )?,
+            sturdy_data.rate_prec,
+        )?,
+        zero_util_rate,
+    )?;
+
+    let new_rate_per_sec = if utilization < sturdy_data.vertex_utilization {
+        to_u64(add(
+            zero_util_rate,
+            div(
+                mul(utilization, sub(vertex_interest, zero_util_rate)?)?,
+                sturdy_data.vertex_utilization,
+            )?,
+        )?)?
+    } else {
+        to_u64(add(
+            vertex_interest,
+            div(
+                mul(
+                    sub(utilization, sturdy_data.vertex_utilization)?,
+                    sub(full_utilization_interest, vertex_interest)?,
+                )?,
+                sub(sturdy_data.util_prec, sturdy_data.vertex_utilization)?,
+            )?,
+        )?)?
+    };
+
+    Ok((new_rate_per_sec, new_full_utilization_interest))
+}
+
+/// Lender APR, net of the protocol fee, of a pair once `delta` is added to,
+/// or removed from, its assets.
+pub fn apr_after_debt_change(
+    sturdy_data: SturdyDataParams,
+    delta: I256,
+) -> Result<U256, AllocationError> {
+    rate_model::apr_after_debt_change(&sturdy_data, &sturdy_data, delta)
+}
+
+/// Rate per second earned by the lenders of a pair whose borrowers pay
+/// `borrow_rate` at `utilization`: the interest is spread over all the pair's
+/// assets and the protocol keeps its fee.
+///
+/// The result never decreases as utilization grows when the borrow rate does
+/// not, so lender rates can be searched by bisection like borrow rates.
+pub fn supply_rate(
+    borrow_rate: u64,
+    utilization: U256,
+    sturdy_data: &SturdyDataParams,
+) -> Result<u64, AllocationError> {
+    let fee_precision = U256::from(FEE_PRECISION);
+    let lender_share = sub(fee_precision, sturdy_data.fee_to_protocol_rate)?;
+    to_u64(div(
+        mul(mul(U256::from(borrow_rate), utilization)?, lender_share)?,
+        mul(sturdy_data.util_prec, fee_precision)?,
+    )?)
+}
+
+/// Utilization of a pair, in `util_prec`, once `delta` is added to, or
+/// removed from, its assets.
+pub fn utilization_after_debt_change(
+    sturdy_data: SturdyDataParams,
+    delta: I256,
+) -> Result<U256, AllocationError> {
+    let asset_amount = to_u256(
+        to_i256(sturdy_data.total_asset)?
+            .checked_add(delta)
+            .ok_or(AllocationError::Overflow)?,
+    )?;
+    Ok(if asset_amount == U256::from(0) {
+        U256::from(0)
+    } else {
+        div(
+            mul(sturdy_data.util_prec, sturdy_data.total_borrow)?,
+            asset_amount,
+        )?
+    })
+}
+
+/// Borrow rate per second of a pair accruing interest at `utilization`.
+///
+/// The rate never decreases as utilization grows, which is what lets the
+/// solver search it by bisection.
+pub fn rate_at_utilization(
+    sturdy_data: SturdyDataParams,
+    utilization: U256,
+) -> Result<u64, AllocationError> {
+    let delta_time = sub(sturdy_data.cur_timestamp, sturdy_data.last_timestamp)?;
+    let (rate_per_sec, _) = get_new_rate(delta_time, utilization, sturdy_data)?;
+    Ok(rate_per_sec)
+}
+
+/// Average borrow rate per second of a pair held at `utilization` for the
+/// next `horizon` seconds.
+///
+/// Outside the target range, the full utilization rate keeps drifting from its
+/// last update, as `VariableInterestRate` would compute it at each second of
+/// the horizon. The average is taken with the trapezoidal rule over
+/// [HORIZON_INTERVALS] intervals, one rate evaluation per interval bound,
+/// and equals [rate_at_utilization] for a zero horizon. Each sample never
+/// decreases as utilization grows, and neither does their average.
+pub fn average_rate_at_utilization(
+    sturdy_data: SturdyDataParams,
+    utilization: U256,
+    horizon: U256,
+) -> Result<u64, AllocationError> {
+    if horizon == U256::from(0) {
+        return rate_at_utilization(sturdy_data, utilization);
+    }
+
+    let elapsed = sub(sturdy_data.cur_timestamp, sturdy_data.last_timestamp)?;
+    let intervals = U256::from(HORIZON_INTERVALS);
+    let mut weighted_sum = U256::from(0);
+    for step in 0..=HORIZON_INTERVALS {
+        let offset = div(mul(horizon, U256::from(step))?, intervals)?;
+        let (rate_per_sec, _) = get_new_rate(add(elapsed, offset)?, utilization, sturdy_data)?;
+        let weight = if step == 0 || step == HORIZON_INTERVALS {
+            1
+        } else {
+            2
+        };
+        weighted_sum = add(
+            weighted_sum,
+            mul(U256::from(rate_per_sec), U256::from(weight))?,
+        )?;
+    }
+    to_u64(div(weighted_sum, mul(intervals, U256::from(2))?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::pair;
+
+    /// Pair following one of the two curves of `VariableInterestRate.t.sol`,
+    /// `delta_time` seconds after its last update at `full_utilization_rate`.
+    fn curve(index: usize, delta_time: u64, full_utilization_rate: u64) -> SturdyDataParams {
+        let mut sturdy_data = pair(0, 0, full_utilization_rate, 0, 0);
+        sturdy_data.cur_timestamp = U256::from(delta_time);
+        if index == 1 {
+            sturdy_data.vertex_utilization = U256::from(80000);
+            sturdy_data.vertex_rate_percent = U256::from(500000000000000000u64);
+            sturdy_data.min_target_util = U256::from(50000);
+            sturdy_data.max_target_util = U256::from(90000);
+            sturdy_data.zero_util_rate = U256::ZERO;
+            sturdy_data.min_full_util_rate = U256::from(1000000000);
+            sturdy_data.max_full_util_rate = U256::from(100000000000000u64);
+            sturdy_data.rate_half_life = U256::from(43200);
+        }
+        sturdy_data
+    }
+
+    #[test]
+    fn matches_variable_interest_rate() -> Result<(), AllocationError> {
+        // (curve, delta time, utilization, full utilization rate, new rate,
+        // new full utilization rate), as returned by the Solidity contract.
+        let cases: [(usize, u64, u64, u64, u64, u64); 12] = [
+            (0, 0, 0, 1582470460, 158247046, 1582470460),
+            (0, 3600, 30000, 1583261695230, 107905724598, 1571475628019),
+            (0, 86400, 74999, 1583261695230, 271543791044, 1583261695089),
+            (0, 86400, 80000, 3164940920000, 578861364385, 3164940920000),
+            (0, 86400, 87500, 1583261695230, 321176885836, 1605251440997),
+            (
+                0,
+                2592000,
+                95000,
+                1583261695230,
+                2152210464654,
+                3164940920000,
+            ),
+            (
+                0,
+                86400,
+                100000,
+                3164940920000,
+                3164940920000,
+                3164940920000,
+            ),
+            (
+                1,
+                3600,
+                30000,
+                50000500000000,
+                9251737253289,
+                49342598684210,
+            ),
+            (
+                1,
+                86400,
+                80000,
+                50000500000000,
+                25000250000000,
+                50000500000000,
+            ),
+            (1, 2592000, 95000, 1000000000, 14000000000, 16000000000),
+            (
+                1,
+                86400,
+                100000,
+                100000000000000,
+                100000000000000,
+                100000000000000,
+            ),
+            (1, 0, 74999, 1000000000, 468743750, 1000000000),
+        ];
+        for (index, delta_time, utilization, full_rate, rate, new_full_rate) in cases {
+            let sturdy_data = curve(index, delta_time, full_rate);
+            assert_eq!(
+                get_new_rate(U256::from(delta_time), U256::from(utilization), sturdy_data)?,
+                (rate, new_full_rate),
+                "curve {index} after {delta_time}s at {utilization}"
+            );
+            assert_eq!(
+                rate_at_utilization(sturdy_data, U256::from(utilization))?,
+                rate
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn averages_drifting_rates_over_the_horizon() -> Result<(), AllocationError> {
+        let sturdy_data = curve(0, 0, 1583261695230);
+        for utilization in [30000, 80000, 95000] {
+            let utilization = U256::from(utilization);
+            let instant = rate_at_utilization(sturdy_data, utilization)?;
+            assert_eq!(
+                average_rate_at_utilization(sturdy_data, utilization, U256::ZERO)?,
+                instant
+            );
+            let average = average_rate_at_utilization(sturdy_data, utilization, U256::from(86400))?;
+            let drift = average.cmp(&instant);
+            let expected = utilization.cmp(&U256::from(80000));
+            assert_eq!(drift, expected, "at {utilization}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn utilization_follows_debt_changes() -> Result<(), AllocationError> {
+        let sturdy_data = pair(0, 0, 0, 1000, 500);
+        assert_eq!(
+            utilization_after_debt_change(sturdy_data, I256::ZERO)?,
+            U256::from(50000)
+        );
+        assert_eq!(
+            utilization_after_debt_change(sturdy_data, I256::from_raw(U256::from(250)))?,
+            U256::from(40000)
+        );
+        assert_eq!(
+            utilization_after_debt_change(sturdy_data, -I256::from_raw(U256::from(1000)))?,
+            U256::ZERO
+        );
+        assert_eq!(
+            utilization_after_debt_change(sturdy_data, -I256::from_raw(U256::from(1001))),
+            Err(AllocationError::Overflow)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn supply_rate_leaves_out_idle_assets_and_protocol_fee() -> Result<(), AllocationError> {
+        let mut sturdy_data = pair(0, 0, 0, 0, 0);
+        assert_eq!(supply_rate(1000, U256::from(50000), &sturdy_data)?, 500);
+        sturdy_data.fee_to_protocol_rate = U256::from(10000);
+        assert_eq!(supply_rate(1000, U256::from(50000), &sturdy_data)?, 450);
+        Ok(())
+    }
+}
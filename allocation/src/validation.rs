@@ -0,0 +1,180 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks run on a decoded input before any allocation math.
+
+use alloy_primitives::U256;
+
+use crate::{error::AllocationError, optimizer::AllocationInput, sturdy::FEE_PRECISION};
+
+/// Reject inputs the allocation math cannot handle, with the reason as a
+/// specific [AllocationError] rather than an overflow or a division by zero
+/// deep in the rate computations.
+///
+/// Checks run in the order of the error codes, so an input breaking several
+/// of them reports the lowest code.
+pub fn validate_input(input: &AllocationInput) -> Result<(), AllocationError> {
+    let strategy_count = input.initial_datas.len();
+    let optional_len_matches = |len: usize| len == 0 || len == strategy_count;
+    if input.strategy_datas.len() != strategy_count
+        || input.sturdy_datas.len() != strategy_count
+        || !optional_len_matches(input.constraints.min_debts.len())
+        || !optional_len_matches(input.rate_models.len())
+    {
+        return Err(AllocationError::LengthMismatch);
+    }
+
+    for (j, initial_data) in input.initial_datas.iter().enumerate() {
+        if input.initial_datas[..j]
+            .iter()
+            .any(|other| other.strategy == initial_data.strategy)
+        {
+            return Err(AllocationError::DuplicateStrategy);
+        }
+    }
+
+    if input.total_available_amount < input.total_initial_amount {
+        return Err(AllocationError::AvailableBelowInitial);
+    }
+
+    if input.chunk_count == 0 {
+        return Err(AllocationError::ZeroChunkCount);
+    }
+
+    if input
+        .sturdy_datas
+        .iter()
+        .any(|sturdy_data| sturdy_data.util_prec <= sturdy_data.max_target_util)
+    {
+        return Err(AllocationError::InvalidTargetUtilization);
+    }
+
+    if input
+        .sturdy_datas
+        .iter()
+        .any(|sturdy_data| sturdy_data.cur_timestamp < sturdy_data.last_timestamp)
+    {
+        return Err(AllocationError::TimestampBeforeLastUpdate);
+    }
+
+    if input
+        .sturdy_datas
+        .iter()
+        .any(|sturdy_data| sturdy_data.fee_to_protocol_rate > U256::from(FEE_PRECISION))
+    {
+        return Err(AllocationError::InvalidProtocolFee);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        fixtures::{market, units, vault},
+        rate_model::RateModelParams,
+    };
+
+    fn validated(change: impl FnOnce(&mut AllocationInput)) -> Result<(), AllocationError> {
+        let mut input = vault(
+            units(1000),
+            &[(market(1000, 500, 0), 1000), (market(1000, 500, 0), 1000)],
+        );
+        change(&mut input);
+        validate_input(&input)
+    }
+
+    #[test]
+    fn requires_one_entry_per_strategy() {
+        assert_eq!(
+            validated(|input| input.constraints.min_debts = vec![U256::ZERO; 2]),
+            Ok(())
+        );
+        assert_eq!(
+            validated(|input| input.rate_models = vec![RateModelParams::default()]),
+            Err(AllocationError::LengthMismatch)
+        );
+        assert_eq!(
+            validated(|input| input.sturdy_datas.truncate(1)),
+            Err(AllocationError::LengthMismatch)
+        );
+        assert_eq!(
+            validated(|input| input.initial_datas[1].strategy = input.initial_datas[0].strategy),
+            Err(AllocationError::DuplicateStrategy)
+        );
+    }
+
+    #[test]
+    fn checks_amounts_and_chunks_at_their_limits() {
+        assert_eq!(
+            validated(|input| input.total_initial_amount = input.total_available_amount),
+            Ok(())
+        );
+        assert_eq!(
+            validated(|input| input.total_initial_amount = units(1000) + U256::from(1)),
+            Err(AllocationError::AvailableBelowInitial)
+        );
+        assert_eq!(validated(|input| input.chunk_count = 1), Ok(()));
+        assert_eq!(
+            validated(|input| input.chunk_count = 0),
+            Err(AllocationError::ZeroChunkCount)
+        );
+    }
+
+    #[test]
+    fn checks_pairs_at_their_limits() {
+        assert_eq!(
+            validated(|input| input.sturdy_datas[1].max_target_util = U256::from(99999)),
+            Ok(())
+        );
+        assert_eq!(
+            validated(|input| input.sturdy_datas[1].max_target_util = U256::from(100000)),
+            Err(AllocationError::InvalidTargetUtilization)
+        );
+        assert_eq!(
+            validated(|input| input.sturdy_datas[1].last_timestamp = U256::from(1697739119)),
+            Ok(())
+        );
+        assert_eq!(
+            validated(|input| input.sturdy_datas[1].last_timestamp = U256::from(1697739120)),
+            Err(AllocationError::TimestampBeforeLastUpdate)
+        );
+        assert_eq!(
+            validated(
+                |input| input.sturdy_datas[0].fee_to_protocol_rate = U256::from(FEE_PRECISION)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            validated(|input| {
+                input.sturdy_datas[0].fee_to_protocol_rate = U256::from(FEE_PRECISION + 1)
+            }),
+            Err(AllocationError::InvalidProtocolFee)
+        );
+    }
+
+    #[test]
+    fn reports_the_lowest_code() {
+        assert_eq!(
+            validated(|input| {
+                input.chunk_count = 0;
+                input.strategy_datas.pop();
+            }),
+            Err(AllocationError::LengthMismatch)
+        );
+    }
+}
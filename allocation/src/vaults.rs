@@ -0,0 +1,207 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Allocation of several vaults, possibly sharing strategies, in one run.
+
+use alloc::vec::Vec;
+
+use alloy_primitives::{Address, I256, U256};
+
+use crate::{
+    actions::{Direction, RebalanceAction},
+    error::{signed_add, to_i256, to_u256, AllocationError},
+    optimizer::{optimal_allocation, AllocationInput, AllocationResult},
+};
+
+/// Compute the allocation of every vault of `inputs`, in input order.
+///
+/// Vaults are allocated one after the other. The debt moves of a successful
+/// vault are applied to the pair of every later strategy with the same
+/// address, so that two vaults depositing into a shared strategy see the
+/// utilization left by each other rather than both counting on the same
+/// rates. A vault that fails or does not improve its objective moves no debt.
+/// Failures are returned as [AllocationResult::failure] of their vault and do
+/// not stop the following vaults.
+pub fn optimal_allocations(inputs: &[AllocationInput]) -> Vec<AllocationResult> {
+    allocate_in_order(inputs.iter().cloned().map(Ok))
+}
+
+/// Like [optimal_allocations], for vaults whose input may already have
+/// failed: such a vault keeps its failure and moves no debt.
+pub(crate) fn allocate_in_order(
+    inputs: impl IntoIterator<Item = Result<AllocationInput, AllocationResult>>,
+) -> Vec<AllocationResult> {
+    let mut moved_debts: Vec<(Address, I256)> = Vec::new();
+    let mut results = Vec::new();
+    for input in inputs {
+        results.push(match input {
+            Ok(input) => with_moved_debts(&input, &moved_debts)
+                .and_then(|input| {
+                    let result = optimal_allocation(&input)?;
+                    record_moves(&mut moved_debts, &result.actions)?;
+                    Ok(result)
+                })
+                .unwrap_or_else(|error| {
+                    vault_failure(error, input.vault, input.snapshot_timestamp)
+                }),
+            Err(failure) => failure,
+        });
+    }
+    results
+}
+
+/// [AllocationResult::failure] bound to the snapshot of `vault`.
+pub(crate) fn vault_failure(
+    error: AllocationError,
+    vault: Address,
+    snapshot_timestamp: U256,
+) -> AllocationResult {
+    let mut result = AllocationResult::failure(error);
+    result.vault = vault;
+    result.snapshot_timestamp = snapshot_timestamp;
+    result
+}
+
+/// Add the debt moved by `actions` to `moved_debts`, leaving it untouched on
+/// error.
+fn record_moves(
+    moved_debts: &mut Vec<(Address, I256)>,
+    actions: &[RebalanceAction],
+) -> Result<(), AllocationError> {
+    let mut updated = moved_debts.clone();
+    for action in actions {
+        let amount = to_i256(action.amount)?;
+        let delta = match action.direction {
+            Direction::Withdraw => -amount,
+            Direction::Deposit => amount,
+        };
+        match updated
+            .iter_mut()
+            .find(|(strategy, _)| *strategy == action.strategy)
+        {
+            Some((_, moved)) => *moved = signed_add(*moved, delta)?,
+            None => updated.push((action.strategy, delta)),
+        }
+    }
+    *moved_debts = updated;
+    Ok(())
+}
+
+/// `input` with the debt already moved into each of its strategies added to
+/// the total assets of the strategy's pair.
+fn with_moved_debts(
+    input: &AllocationInput,
+    moved_debts: &[(Address, I256)],
+) -> Result<AllocationInput, AllocationError> {
+    let mut input = input.clone();
+    for (initial_data, sturdy_data) in input.initial_datas.iter().zip(&mut input.sturdy_datas) {
+        if let Some((_, moved)) = moved_debts
+            .iter()
+            .find(|(strategy, _)| *strategy == initial_data.strategy)
+        {
+            let total_asset = to_i256(sturdy_data.total_asset)?
+                .checked_add(*moved)
+                .ok_or(AllocationError::Overflow)?;
+            sturdy_data.total_asset = to_u256(total_asset)?;
+        }
+    }
+    Ok(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{two_markets, units};
+
+    fn vault(last_byte: u8) -> AllocationInput {
+        let mut input = two_markets(units(800), [2000, 2000]);
+        input.vault = Address::with_last_byte(last_byte);
+        input
+    }
+
+    #[test]
+    fn later_vaults_see_earlier_deposits() -> Result<(), AllocationError> {
+        let results = optimal_allocations(&[vault(1), vault(2)]);
+        // The first vault puts its 800 units in the first market.
+        assert_eq!(results[0], optimal_allocation(&vault(1))?);
+        assert_eq!(
+            results[0].actions,
+            [RebalanceAction {
+                strategy: Address::with_last_byte(1),
+                direction: Direction::Deposit,
+                amount: units(800),
+            }]
+        );
+
+        let second = &results[1];
+        assert_eq!(second.vault, Address::with_last_byte(2));
+        // 500 borrowed of 1800 units, then of 1000.
+        assert_eq!(second.reports[0].current_utilization, U256::from(27777));
+        assert_eq!(second.reports[1].current_utilization, U256::from(50000));
+        assert_ne!(
+            second.allocations,
+            optimal_allocation(&vault(2))?.allocations
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failed_vaults_move_no_debt() -> Result<(), AllocationError> {
+        let mut failing = vault(1);
+        failing.chunk_count = 0;
+        let results = optimal_allocations(&[failing.clone(), vault(2)]);
+        assert_eq!(
+            results[0],
+            vault_failure(
+                AllocationError::ZeroChunkCount,
+                failing.vault,
+                failing.snapshot_timestamp
+            )
+        );
+        assert_eq!(results[1], optimal_allocation(&vault(2))?);
+        Ok(())
+    }
+
+    #[test]
+    fn records_moves_per_strategy() -> Result<(), AllocationError> {
+        let strategy = Address::with_last_byte(1);
+        let action = |direction, amount| RebalanceAction {
+            strategy,
+            direction,
+            amount,
+        };
+        let mut moved_debts = alloc::vec![(strategy, I256::MAX)];
+        assert_eq!(
+            record_moves(
+                &mut moved_debts,
+                &[
+                    action(Direction::Withdraw, U256::from(1)),
+                    action(Direction::Deposit, U256::from(2)),
+                ]
+            ),
+            Err(AllocationError::Overflow)
+        );
+        assert_eq!(moved_debts, [(strategy, I256::MAX)]);
+
+        record_moves(
+            &mut moved_debts,
+            &[action(Direction::Withdraw, U256::from(3))],
+        )?;
+        assert_eq!(
+            moved_debts,
+            [(strategy, I256::MAX - I256::from_raw(U256::from(3)))]
+        );
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@
 pragma solidity 0.8.21;
 
 import {Ownable} from "../lib/openzeppelin-contracts/contracts/access/Ownable.sol";
+import {ILendingMarketStrategy} from "./interfaces/ILendingMarketStrategy.sol";
 import {ISturdySiloStrategy} from "./interfaces/ISturdySiloStrategy.sol";
 import {ISturdyPair} from "./interfaces/ISturdyPair.sol";
 import {IDebtManager} from "./interfaces/IDebtManager.sol";
@@ -31,7 +32,133 @@ interface ISturdyStrategyRate {
 
 contract ZKOptimalAllocation is Ownable, BonsaiCallbackReceiver {
     error AG_INVALID_CONFIGURATION();
+    error AG_UNKNOWN_INPUT();
+    error AG_STALE_RESULT();
+    error AG_NO_VAULT();
+    error AG_TOO_MANY_VAULTS();
+
+    /// @notice Quantity maximized by the guest, from the rates lenders earn net of each pair's protocol fee.
+    /// @dev TotalInterest accounts for the rate drop on debt already deposited in a strategy,
+    ///      MarginalRate only equalizes the lender rates of the strategies.
+    enum Objective {
+        TotalInterest,
+        MarginalRate
+    }
+
+    /// @notice Emitted when the guest could not compute the allocation of a vault.
+    /// @param vault Vault of the failed result, zero when the input could not be decoded.
+    /// @param errorCode 1: invalid input, 2: no strategy can take more debt, 3: arithmetic overflow,
+    ///        4: infeasible constraints or unfunded deposits. Inputs rejected before any computation report
+    ///        5: strategy lists of different lengths, 6: duplicate strategy, 7: total available below total initial
+    ///        amount, 8: zero chunk count, 9: max target utilization not below UTIL_PREC, 10: pair timestamp before
+    ///        its last rate update, 11: protocol fee above its 1e5 precision.
+    event AllocationFailed(address indexed vault, uint8 errorCode);
+
+    /// @notice Vault policy limits enforced by the guest on top of each strategy's max debt.
+    /// @param minDebts Minimum debt of each strategy, in request order. Empty when unset.
+    /// @param maxShareBps Maximum debt of a single strategy, in basis points of the available assets. Zero when unset.
+    /// @param minIdle Assets left idle on top of the vault's minimum total idle.
+    /// @param limitWithdrawals Whether withdrawals are capped by the pair's available liquidity.
+    struct AllocationConstraints {
+        uint256[] minDebts;
+        uint256 maxShareBps;
+        uint256 minIdle;
+        bool limitWithdrawals;
+    }
+
+    /// @notice Cost of a rebalance, weighed against the interest it earns. Disabled while holdingHorizon is zero.
+    /// @param gasCost Expected gas cost of the rebalance, in wei.
+    /// @param assetPrice Price of one asset base unit in wei, scaled by 1e18.
+    /// @param holdingHorizon Seconds the new allocation is expected to stay in place.
+    /// @param minNetGain Net gain, in asset base units, the rebalance must beat.
+    struct CostModel {
+        uint256 gasCost;
+        uint256 assetPrice;
+        uint256 holdingHorizon;
+        uint256 minNetGain;
+    }
+
+    /// @notice Curve mapping the utilization of a strategy's pair to its borrow rate.
+    /// @dev Sturdy follows the pair's VariableInterestRate contract, LinearKink is an Aave and Compound style jump
+    ///      rate and Fixed pays baseRate whatever the utilization. Strategies of the other kinds are read through
+    ///      ILendingMarketStrategy rather than a Sturdy pair.
+    enum RateModelKind {
+        Sturdy,
+        LinearKink,
+        Fixed
+    }
+
+    /// @notice Rate model of a strategy. Fields a model does not use are ignored.
+    /// @param baseRate Rate per second, in 1e18, at zero utilization or of a fixed model.
+    /// @param slope1 Rate per second added between zero utilization and the kink.
+    /// @param slope2 Rate per second added between the kink and full utilization.
+    /// @param kink Utilization, in the pair's UTIL_PREC, at which slope2 takes over.
+    struct RateModelParams {
+        RateModelKind kind;
+        uint256 baseRate;
+        uint256 slope1;
+        uint256 slope2;
+        uint256 kink;
+    }
+
+    enum RebalanceDirection {
+        Withdraw,
+        Deposit
+    }
+
+    /// @notice Debt move of one strategy, to be executed in the order the guest commits them.
+    /// @dev Withdrawals come first, so every deposit is funded by the withdrawals before it or by idle assets.
+    /// @param amount Assets moved, never zero.
+    struct RebalanceAction {
+        address strategy;
+        RebalanceDirection direction;
+        uint256 amount;
+    }
+
+    /// @notice APR and yearly interest of the proposed allocation and of the current debts.
+    struct Performance {
+        uint256 newAPR;
+        uint256 curAPR;
+        uint256 newInterest;
+        uint256 curInterest;
+    }
+
+    /// @notice Current and projected state of a strategy's pair, once its debt moves to the proposed one.
+    /// @param targetDebt Debt proposed by the allocation, the current debt when the strategy is left out of it.
+    /// @param utilPrec Precision of the utilizations.
+    /// @param currentRatePerSec Borrow rate per second, in 1e18 precision, and likewise for the projected one.
+    /// @param currentAPR Lender APR, net of the protocol fee, in 1e18 precision, and likewise for the projected one.
+    struct StrategyReport {
+        address strategy;
+        uint256 currentDebt;
+        uint256 targetDebt;
+        uint256 utilPrec;
+        uint256 currentUtilization;
+        uint256 projectedUtilization;
+        uint256 currentRatePerSec;
+        uint256 projectedRatePerSec;
+        uint256 currentAPR;
+        uint256 projectedAPR;
+    }
 
+    /// @notice State a result was computed from.
+    /// @param vault Vault the guest input was read from.
+    /// @param inputHash Keccak hash of the guest input.
+    /// @param timestamp Block timestamp at which the guest input was read.
+    struct Snapshot {
+        address vault;
+        bytes32 inputHash;
+        uint256 timestamp;
+    }
+
+    /// @notice Vault and block timestamp a guest input was read at.
+    struct InputSnapshot {
+        address vault;
+        uint256 timestamp;
+    }
+
+    /// @notice Rate state and curve constants of a strategy's pair.
+    /// @param feeToProtocolRate Share of the interest taken by the protocol, in 1e5 precision.
     struct SturdyStrategyDataParams {
         uint256 curTimestamp;
         uint256 lastTimestamp;
@@ -50,86 +177,398 @@ contract ZKOptimalAllocation is Ownable, BonsaiCallbackReceiver {
         uint256 VERTEX_RATE_PERCENT;
         uint256 RATE_PREC;
         bool isInterestPaused;
+        uint256 feeToProtocolRate;
+    }
+
+    /// @notice Vault to allocate, with the arguments startOptimalAllocation takes for it.
+    /// @param chunkCount Limit on the bisection steps of the guest solver, raised to at least 64. Must not be zero.
+    struct VaultRequest {
+        IVault vault;
+        uint256 chunkCount;
+        uint256 totalInitialAmount;
+        IDebtManager.StrategyAllocation[] initialDatas;
+    }
+
+    /// @notice Guest input of one vault.
+    /// @param chunkCount Limit on the bisection steps of the guest solver, raised to at least 64. Must not be zero.
+    struct VaultInput {
+        uint256 chunkCount;
+        uint256 totalInitialAmount;
+        uint256 totalAvailable;
+        IDebtManager.StrategyAllocation[] initialDatas;
+        IVault.StrategyParams[] strategyDatas;
+        SturdyStrategyDataParams[] sturdyDatas;
+        uint8 objective;
+        AllocationConstraints constraints;
+        CostModel costModel;
+        InputSnapshot snapshot;
+        RateModelParams[] rateModels;
+        uint256 rateHorizon;
+    }
+
+    /// @notice Result of one vault committed by the guest.
+    /// @param allocations Target debts, withdrawals first. Empty unless isSuccess.
+    /// @param actions Debt moves reaching allocations, in execution order. Empty unless isSuccess.
+    /// @param constraints Constraints the result was computed under.
+    /// @param netGain Interest gained over the holding horizon minus the gas cost, in asset base units.
+    /// @param unallocatedAmount Assets left idle, on top of minIdle, because every strategy reached its cap.
+    /// @param errorCode Zero when the allocation was computed, see AllocationFailed.
+    /// @param reports Figures of every strategy, in request order. Empty when errorCode is set.
+    struct VaultResult {
+        IDebtManager.StrategyAllocation[] allocations;
+        RebalanceAction[] actions;
+        Performance performance;
+        AllocationConstraints constraints;
+        int256 netGain;
+        uint256 unallocatedAmount;
+        bool isSuccess;
+        uint8 errorCode;
+        Snapshot snapshot;
+        StrategyReport[] reports;
     }
 
     /// @notice Image ID of the only zkVM binary to accept callbacks from.
     bytes32 public immutable fibImageId;
 
     /// @notice Gas limit set on the callback from Bonsai.
-    /// @dev Should be set to the maximum amount of gas your callback might reasonably consume.
-    uint64 private constant BONSAI_CALLBACK_GAS_LIMIT = 1000000;
+    /// @dev Should be set to the maximum amount of gas your callback might reasonably consume. Storing the actions and
+    ///      strategy reports of a few strategies alone takes over a million. Multi-vault requests get it once per
+    ///      vault.
+    uint64 private constant BONSAI_CALLBACK_GAS_LIMIT = 3000000;
+
+    /// @notice Maximum number of vaults in a multi-vault request.
+    /// @dev Bounds the callback gas limit of a request to 15M, half a 30M block.
+    uint256 public constant MAX_VAULTS_PER_REQUEST = 5;
+
+
+    /// @notice Last result received for each vault.
+    mapping(address => VaultResult) private _results;
+
+    /// @notice Hashes of the guest inputs whose result has not been received yet.
+    mapping(bytes32 => bool) public pendingInputs;
+
+    /// @notice Maximum age, in seconds, of the snapshot a successful result was computed from.
+    uint256 public maxResultAge = 1 hours;
+
+    /// @notice Objective passed to the guest with every request.
+    Objective public objective;
 
+    /// @notice Minimum debt of each strategy passed to the guest.
+    mapping(address => uint256) public minDebts;
+    uint256 public maxShareBps;
+    uint256 public minIdle;
+    bool public limitWithdrawals;
+    CostModel public costModel;
 
-    IDebtManager.StrategyAllocation[] private _allocationDatas;
-    uint256 private _newAPR;
-    uint256 private _curAPR;
-    bool private _isSuccess;
+    /// @notice Rate model of each strategy passed to the guest, Sturdy when unset.
+    mapping(address => RateModelParams) public rateModels;
+
+    /// @notice Seconds over which the guest averages Sturdy rates as their full utilization rate drifts, zero to use
+    ///         the rates at the time of the request.
+    uint256 public rateHorizon;
 
     /// @notice Initialize the contract, binding it to a specified Bonsai relay and RISC Zero guest image.
     constructor(IBonsaiRelay bonsaiRelay, bytes32 _fibImageId) BonsaiCallbackReceiver(bonsaiRelay) {
         fibImageId = _fibImageId;
     }
 
+    function setObjective(Objective _objective) external onlyOwner {
+        objective = _objective;
+    }
+
+    function setMinDebt(address strategy, uint256 minDebt) external onlyOwner {
+        minDebts[strategy] = minDebt;
+    }
+
+    function setAllocationLimits(uint256 _maxShareBps, uint256 _minIdle, bool _limitWithdrawals) external onlyOwner {
+        if (_maxShareBps > 10_000) revert AG_INVALID_CONFIGURATION();
+
+        maxShareBps = _maxShareBps;
+        minIdle = _minIdle;
+        limitWithdrawals = _limitWithdrawals;
+    }
+
+    function setCostModel(CostModel calldata _costModel) external onlyOwner {
+        if (_costModel.gasCost != 0 && _costModel.assetPrice == 0) revert AG_INVALID_CONFIGURATION();
+
+        costModel = _costModel;
+    }
+
+    /// @dev A LinearKink kink must lie within the utilization precision of the strategy's market.
+    function setRateModel(address strategy, RateModelParams calldata rateModel) external onlyOwner {
+        if (rateModel.kind == RateModelKind.LinearKink) {
+            (, , uint256 utilPrec, , , , ) = ILendingMarketStrategy(strategy).marketAccounting();
+            if (rateModel.kink == 0 || rateModel.kink > utilPrec) revert AG_INVALID_CONFIGURATION();
+        }
+
+        rateModels[strategy] = rateModel;
+    }
+
+    function setRateHorizon(uint256 _rateHorizon) external onlyOwner {
+        rateHorizon = _rateHorizon;
+    }
+
+    function setMaxResultAge(uint256 _maxResultAge) external onlyOwner {
+        maxResultAge = _maxResultAge;
+    }
+
+    /// @notice Accept the result of an input sent to the relay off-chain rather than through startOptimalAllocation.
+    function expectResult(bytes32 inputHash) external onlyOwner {
+        pendingInputs[inputHash] = true;
+    }
+
     function startOptimalAllocation(
         IVault vault,
         uint256 chunkCount,
         uint256 totalInitialAmount,
         IDebtManager.StrategyAllocation[] calldata initialDatas
     ) external {
-        uint256 strategyCount = initialDatas.length;
-        IVault.StrategyParams[] memory strategyDatas = new IVault.StrategyParams[](strategyCount);
-        SturdyStrategyDataParams[] memory sturdyDatas = new SturdyStrategyDataParams[](strategyCount);
-        uint256 totalAvailable;
+        _requestCallback(
+            optimalAllocationInput(vault, chunkCount, totalInitialAmount, initialDatas),
+            BONSAI_CALLBACK_GAS_LIMIT
+        );
+    }
 
-        if (address(vault) != address(0)) {
-            for (uint256 i; i < strategyCount; ++i) {
-                strategyDatas[i] = vault.strategies(initialDatas[i].strategy);
-                sturdyDatas[i] = _getSturdyStrategyData(initialDatas[i].strategy);
+    /// @notice Request the allocation of several vaults with a single proof and callback.
+    /// @dev Vaults are allocated in request order, each one seeing the pair utilization left by the debt moves of
+    ///      the vaults before it on the strategies they share. Reverts for more than MAX_VAULTS_PER_REQUEST vaults.
+    function startMultiVaultAllocation(VaultRequest[] calldata requests) external {
+        _requestCallback(multiVaultAllocationInput(requests), BONSAI_CALLBACK_GAS_LIMIT * uint64(requests.length));
+    }
+
+    /// @notice Build the guest input for the current state of `vault`.
+    /// @dev Exposed so that off-chain keepers can execute the guest locally before requesting a proof.
+    function optimalAllocationInput(
+        IVault vault,
+        uint256 chunkCount,
+        uint256 totalInitialAmount,
+        IDebtManager.StrategyAllocation[] calldata initialDatas
+    ) public view returns (bytes memory) {
+        VaultInput[] memory vaults = new VaultInput[](1);
+        vaults[0] = _vaultInput(vault, chunkCount, totalInitialAmount, initialDatas);
+        return abi.encode(vaults);
+    }
+
+    /// @notice Build the guest input for the current state of every vault of `requests`, in request order.
+    function multiVaultAllocationInput(VaultRequest[] calldata requests) public view returns (bytes memory) {
+        uint256 length = requests.length;
+        if (length == 0) revert AG_NO_VAULT();
+        if (length > MAX_VAULTS_PER_REQUEST) revert AG_TOO_MANY_VAULTS();
+
+        VaultInput[] memory vaults = new VaultInput[](length);
+        for (uint256 i; i < length; ++i) {
+            VaultRequest calldata request = requests[i];
+            vaults[i] = _vaultInput(
+                request.vault,
+                request.chunkCount,
+                request.totalInitialAmount,
+                request.initialDatas
+            );
+        }
+        return abi.encode(vaults);
+    }
+
+    /// @notice Callback function logic for processing verified journals from Bonsai, one result per requested vault.
+    /// @dev Reverts unless the results were computed for a pending input. Successful results must also have been
+    ///      computed from a snapshot at most maxResultAge old; failed results carry no allocation and are recorded
+    ///      regardless of their age.
+    function onResult(VaultResult[] calldata results) external onlyBonsaiCallback(fibImageId) {
+        bytes32 inputHash = results[0].snapshot.inputHash;
+        if (!pendingInputs[inputHash]) revert AG_UNKNOWN_INPUT();
+        delete pendingInputs[inputHash];
+
+        uint256 length = results.length;
+        for (uint256 i; i < length; ++i) {
+            VaultResult calldata result = results[i];
+            if (result.snapshot.inputHash != inputHash) revert AG_UNKNOWN_INPUT();
+            if (result.isSuccess && block.timestamp > result.snapshot.timestamp + maxResultAge) {
+                revert AG_STALE_RESULT();
+            }
+
+            _storeResult(result);
+            if (result.errorCode != 0) {
+                emit AllocationFailed(result.snapshot.vault, result.errorCode);
             }
-            totalAvailable = vault.totalAssets() - vault.minimum_total_idle();
         }
 
+        // isSuccess = true then, Perform allocation via debt manager
+    }
+
+    function getResult(
+        address vault
+    ) external view returns (IDebtManager.StrategyAllocation[] memory, uint256, uint256, bool) {
+        VaultResult storage result = _results[vault];
+        return (
+            result.allocations,
+            result.performance.newAPR,
+            result.performance.curAPR,
+            result.isSuccess
+        );
+    }
+
+    /// @notice Debt moves reaching the last allocation of `vault`, in execution order.
+    function getActions(address vault) external view returns (RebalanceAction[] memory) {
+        return _results[vault].actions;
+    }
+
+    /// @notice Current and projected utilization, rate and APR of each strategy of the last result of `vault`, in
+    ///         input order.
+    function getStrategyReports(address vault) external view returns (StrategyReport[] memory) {
+        return _results[vault].reports;
+    }
+
+    /// @notice Yearly interest, in asset units, of the proposed allocation and of the current debts of `vault`.
+    function getInterest(address vault) external view returns (uint256, uint256) {
+        Performance storage performance = _results[vault].performance;
+        return (performance.newInterest, performance.curInterest);
+    }
+
+    /// @notice Constraints the last result of `vault` was computed under.
+    function getAppliedConstraints(address vault) external view returns (AllocationConstraints memory) {
+        return _results[vault].constraints;
+    }
+
+    /// @notice State the last result of `vault` was computed from.
+    function getSnapshot(address vault) external view returns (Snapshot memory) {
+        return _results[vault].snapshot;
+    }
+
+    /// @notice Interest gained over the holding horizon minus the gas cost of the last result of `vault`, in asset
+    ///         base units.
+    function getNetGain(address vault) external view returns (int256) {
+        return _results[vault].netGain;
+    }
+
+    /// @notice Assets the last result of `vault` leaves idle because its strategies cannot take them.
+    function getUnallocatedAmount(address vault) external view returns (uint256) {
+        return _results[vault].unallocatedAmount;
+    }
+
+    /// @notice Error code of the last result of `vault`, zero when the allocation was computed.
+    function getErrorCode(address vault) external view returns (uint8) {
+        return _results[vault].errorCode;
+    }
+
+    function _requestCallback(bytes memory input, uint64 gasLimit) internal {
+        pendingInputs[keccak256(input)] = true;
+
         bonsaiRelay.requestCallback(
             fibImageId, 
-            abi.encode(
-                chunkCount,
-                totalInitialAmount,
-                totalAvailable,
-                initialDatas,
-                strategyDatas,
-                sturdyDatas
-            ),
+            input,
             address(this), 
             this.onResult.selector, 
-            BONSAI_CALLBACK_GAS_LIMIT
+            gasLimit
         );
     }
 
-    /// @notice Callback function logic for processing verified journals from Bonsai.
-    function onResult(
-        IDebtManager.StrategyAllocation[] calldata allocationDatas, 
-        uint256 newAPR,
-        uint256 curAPR,
-        bool isSuccess
-    ) external onlyBonsaiCallback(fibImageId) {
-        uint256 length = allocationDatas.length;
+    /// @dev Copies the arrays element by element, which calldata to storage assignment does not support for
+    ///      arrays of structs.
+    function _storeResult(VaultResult calldata result) internal {
+        VaultResult storage stored = _results[result.snapshot.vault];
+
+        delete stored.allocations;
+        uint256 length = result.allocations.length;
         for (uint256 i; i < length; ++i) {
-            _allocationDatas.push(allocationDatas[i]);
+            stored.allocations.push(result.allocations[i]);
         }
-        _newAPR = newAPR;
-        _curAPR = curAPR;
-        _isSuccess = isSuccess;
 
-        // isSuccess = true then, Perform allocation via debt manager
+        delete stored.actions;
+        length = result.actions.length;
+        for (uint256 i; i < length; ++i) {
+            stored.actions.push(result.actions[i]);
+        }
+
+        delete stored.reports;
+        length = result.reports.length;
+        for (uint256 i; i < length; ++i) {
+            stored.reports.push(result.reports[i]);
+        }
+
+        stored.performance = result.performance;
+        stored.constraints.minDebts = result.constraints.minDebts;
+        stored.constraints.maxShareBps = result.constraints.maxShareBps;
+        stored.constraints.minIdle = result.constraints.minIdle;
+        stored.constraints.limitWithdrawals = result.constraints.limitWithdrawals;
+        stored.netGain = result.netGain;
+        stored.unallocatedAmount = result.unallocatedAmount;
+        stored.isSuccess = result.isSuccess;
+        stored.errorCode = result.errorCode;
+        stored.snapshot = result.snapshot;
     }
 
-    function getResult() external view returns (IDebtManager.StrategyAllocation[] memory, uint256, uint256, bool) {
-        return (
-            _allocationDatas,
-            _newAPR,
-            _curAPR,
-            _isSuccess
-        );
+    /// @notice Guest input of `vault` for its current state.
+    function _vaultInput(
+        IVault vault,
+        uint256 chunkCount,
+        uint256 totalInitialAmount,
+        IDebtManager.StrategyAllocation[] calldata initialDatas
+    ) internal view returns (VaultInput memory input) {
+        input.chunkCount = chunkCount;
+        input.totalInitialAmount = totalInitialAmount;
+        if (address(vault) != address(0)) {
+            input.totalAvailable = vault.totalAssets() - vault.minimum_total_idle();
+        }
+        input.initialDatas = initialDatas;
+        (
+            input.strategyDatas,
+            input.sturdyDatas,
+            input.constraints,
+            input.rateModels
+        ) = _getStrategyInputs(vault, initialDatas);
+        input.objective = uint8(objective);
+        input.costModel = costModel;
+        input.snapshot = InputSnapshot(address(vault), block.timestamp);
+        input.rateHorizon = rateHorizon;
+    }
+
+    /// @notice Vault accounting, pair data, constraints and rate model of each strategy of `vault`, zero when vault
+    ///         is unset.
+    function _getStrategyInputs(
+        IVault vault,
+        IDebtManager.StrategyAllocation[] calldata initialDatas
+    )
+        internal
+        view
+        returns (
+            IVault.StrategyParams[] memory strategyDatas,
+            SturdyStrategyDataParams[] memory sturdyDatas,
+            AllocationConstraints memory constraints,
+            RateModelParams[] memory strategyRateModels
+        )
+    {
+        uint256 strategyCount = initialDatas.length;
+        strategyDatas = new IVault.StrategyParams[](strategyCount);
+        sturdyDatas = new SturdyStrategyDataParams[](strategyCount);
+        constraints = AllocationConstraints(new uint256[](strategyCount), maxShareBps, minIdle, limitWithdrawals);
+        strategyRateModels = new RateModelParams[](strategyCount);
+        if (address(vault) == address(0)) return (strategyDatas, sturdyDatas, constraints, strategyRateModels);
+
+        for (uint256 i; i < strategyCount; ++i) {
+            address strategy = initialDatas[i].strategy;
+            strategyDatas[i] = vault.strategies(strategy);
+            strategyRateModels[i] = rateModels[strategy];
+            sturdyDatas[i] = strategyRateModels[i].kind == RateModelKind.Sturdy
+                ? _getSturdyStrategyData(strategy)
+                : _getMarketStrategyData(strategy);
+            constraints.minDebts[i] = minDebts[strategy];
+        }
+    }
+
+    /// @notice Accounting of a strategy whose rate model is not Sturdy. The Sturdy curve constants are left zero,
+    ///         since the guest only reads them for the Sturdy model.
+    function _getMarketStrategyData(
+        address strategy
+    ) internal view returns (SturdyStrategyDataParams memory data) {
+        (
+            data.totalAsset,
+            data.totalBorrow,
+            data.UTIL_PREC,
+            data.ratePerSec,
+            data.feeToProtocolRate,
+            data.lastTimestamp,
+            data.isInterestPaused
+        ) = ILendingMarketStrategy(strategy).marketAccounting();
+        data.curTimestamp = block.timestamp;
     }
 
     function _getSturdyStrategyData(
@@ -152,7 +591,7 @@ contract ZKOptimalAllocation is Ownable, BonsaiCallbackReceiver {
     ) internal view returns (SturdyStrategyDataParams memory) {
         (
             ,
-            ,
+            data.feeToProtocolRate,
             data.lastTimestamp,
             data.ratePerSec,
             data.fullUtilizationRate
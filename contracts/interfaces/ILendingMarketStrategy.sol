@@ -0,0 +1,28 @@
+// SPDX-License-Identifier: AGPL-3.0
+pragma solidity 0.8.21;
+
+import {IStrategy} from "./IStrategy.sol";
+
+/// @notice Strategy lending into a market other than a Sturdy pair. Its accounting is read instead of the Sturdy
+///         pair and rate contracts for strategies whose rate model is not Sturdy.
+interface ILendingMarketStrategy is IStrategy {
+    /// @return totalAsset Assets supplied to the market.
+    /// @return totalBorrow Assets borrowed from the market.
+    /// @return utilPrec Precision of the market utilization, which rate model kinks are expressed in.
+    /// @return ratePerSec Borrow rate per second, in 1e18 precision.
+    /// @return feeToProtocolRate Share of the interest taken by the protocol, in 1e5 precision.
+    /// @return lastTimestamp Timestamp of the last interest accrual.
+    /// @return isInterestPaused Whether the market accrues no interest.
+    function marketAccounting()
+        external
+        view
+        returns (
+            uint256 totalAsset,
+            uint256 totalBorrow,
+            uint256 utilPrec,
+            uint256 ratePerSec,
+            uint256 feeToProtocolRate,
+            uint256 lastTimestamp,
+            bool isInterestPaused
+        );
+}
@@ -0,0 +1,532 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Differential tests of the `optimal_allocation` guest against a native run
+//! of the `allocation` crate.
+//!
+//! Every case is a random but valid vault state. The journal committed by the
+//! guest must match the natively encoded result byte for byte, and the result
+//! must satisfy the allocation invariants. Cases are drawn from a fixed seed,
+//! printed with the output of a failing test; set `ALLOCATION_TEST_SEED` to try
+//! another one or replay a failure, and `ALLOCATION_TEST_CASES` to change the
+//! number of cases.
+
+use std::env;
+
+use allocation::{
+    abi::{decode_journal, encode_input, encode_journal, evaluate, VaultResult},
+    get_optimal_allocation, AllocationError, AllocationInput, Constraints, CostModel, Direction,
+    Objective, Position, RateModelKind, RateModelParams, StrategyParams, SturdyDataParams,
+    FEE_PRECISION,
+};
+use alloy_primitives::{Address, U256};
+use anyhow::{anyhow, ensure, Context, Result};
+use methods::OPTIMAL_ALLOCATION_ELF;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use risc0_zkvm::{default_executor, ExecutorEnv};
+
+/// Cases run when `ALLOCATION_TEST_CASES` is unset. Each one executes the
+/// guest, so keep this small.
+const DEFAULT_CASES: usize = 16;
+
+/// Seed used when `ALLOCATION_TEST_SEED` is unset, so that runs are
+/// reproducible.
+const DEFAULT_SEED: u64 = 0x5eed_a110_ca7e;
+
+/// Block timestamp of the generated snapshots.
+const CUR_TIMESTAMP: u64 = 1_697_739_119;
+
+/// Utilization precision of Sturdy pairs.
+const UTIL_PREC: u64 = 100_000;
+
+const MIN_FULL_UTIL_RATE: u64 = 1_582_470_460;
+const MAX_FULL_UTIL_RATE: u64 = 3_164_940_920_000;
+const ZERO_UTIL_RATE: u64 = 158_247_046;
+
+/// Execute the guest on `input` and return its journal, like the relay's
+/// `execute_locally` in dev mode.
+fn execute_locally(input: &[u8]) -> Result<Vec<u8>> {
+    let env = ExecutorEnv::builder()
+        .write_slice(input)
+        .build()
+        .context("Failed to build exec env")?;
+    let session = default_executor()
+        .execute_elf(env, OPTIMAL_ALLOCATION_ELF)
+        .context("Failed to run executor")?;
+    Ok(session.journal.bytes)
+}
+
+/// `10^exponent` asset base units.
+fn units(exponent: u8) -> U256 {
+    U256::from(10).pow(U256::from(exponent))
+}
+
+/// Random amount in `[0, max]`.
+fn amount_up_to(rng: &mut StdRng, max: U256) -> U256 {
+    if max == U256::ZERO {
+        return U256::ZERO;
+    }
+    let fraction = rng.gen_range(0..=1_000_000u64);
+    max * U256::from(fraction) / U256::from(1_000_000)
+}
+
+/// Pair state within the ranges the Sturdy rate model is configured for.
+fn random_pair(rng: &mut StdRng) -> SturdyDataParams {
+    let total_asset = units(rng.gen_range(20..=27)) * U256::from(rng.gen_range(1..=9u64));
+    let utilization = rng.gen_range(0..=99_000u64);
+    let min_target_util = rng.gen_range(50_000..=80_000u64);
+    let max_target_util = rng.gen_range(min_target_util + 5_000..=95_000);
+    let full_utilization_rate = rng.gen_range(MIN_FULL_UTIL_RATE..=MAX_FULL_UTIL_RATE / 100);
+
+    SturdyDataParams {
+        cur_timestamp: U256::from(CUR_TIMESTAMP),
+        last_timestamp: U256::from(CUR_TIMESTAMP - rng.gen_range(0..=30 * 86_400u64)),
+        rate_per_sec: U256::from(rng.gen_range(ZERO_UTIL_RATE..=full_utilization_rate)),
+        full_utilization_rate: U256::from(full_utilization_rate),
+        total_asset,
+        total_borrow: total_asset * U256::from(utilization) / U256::from(UTIL_PREC),
+        util_prec: U256::from(UTIL_PREC),
+        min_target_util: U256::from(min_target_util),
+        max_target_util: U256::from(max_target_util),
+        vertex_utilization: U256::from(rng.gen_range(80_000..=95_000u64)),
+        min_full_util_rate: U256::from(MIN_FULL_UTIL_RATE),
+        max_full_util_rate: U256::from(MAX_FULL_UTIL_RATE),
+        zero_util_rate: U256::from(ZERO_UTIL_RATE),
+        rate_half_life: U256::from(172_800),
+        vertex_rate_percent: U256::from(200_000_000_000_000_000u64),
+        rate_prec: U256::from(1_000_000_000_000_000_000u64),
+        is_interest_paused: rng.gen_ratio(1, 20),
+        fee_to_protocol_rate: U256::from(rng.gen_range(0..=20_000u64)),
+    }
+}
+
+/// Mostly Sturdy models, with the occasional linear kink or fixed rate.
+fn random_rate_model(rng: &mut StdRng) -> RateModelParams {
+    match rng.gen_range(0..6) {
+        0 => RateModelParams {
+            kind: RateModelKind::LinearKink,
+            base_rate: U256::from(ZERO_UTIL_RATE),
+            slope1: U256::from(rng.gen_range(100_000_000..=2_000_000_000u64)),
+            slope2: U256::from(rng.gen_range(2_000_000_000..=50_000_000_000u64)),
+            kink: U256::from(rng.gen_range(50_000..=95_000u64)),
+        },
+        1 => RateModelParams {
+            kind: RateModelKind::Fixed,
+            base_rate: U256::from(rng.gen_range(ZERO_UTIL_RATE..=MIN_FULL_UTIL_RATE)),
+            ..Default::default()
+        },
+        _ => RateModelParams::default(),
+    }
+}
+
+/// Random vault whose available assets mostly fit within the strategies' max
+/// debts.
+fn random_input(rng: &mut StdRng) -> AllocationInput {
+    let strategy_count = rng.gen_range(1..=6);
+    let sturdy_datas: Vec<_> = (0..strategy_count).map(|_| random_pair(rng)).collect();
+
+    let mut initial_datas = Vec::with_capacity(strategy_count);
+    let mut strategy_datas = Vec::with_capacity(strategy_count);
+    for (j, sturdy_data) in sturdy_datas.iter().enumerate() {
+        let max_debt = units(rng.gen_range(18..=24)) * U256::from(rng.gen_range(1..=9u64));
+        // The strategy's deposit is part of the pair's assets.
+        let current_debt = amount_up_to(rng, max_debt.min(sturdy_data.total_asset));
+        let debt = if rng.gen_bool(0.5) {
+            U256::ZERO
+        } else {
+            amount_up_to(rng, current_debt)
+        };
+        initial_datas.push(Position {
+            strategy: Address::repeat_byte(j as u8 + 1),
+            debt,
+        });
+        strategy_datas.push(StrategyParams {
+            activation: U256::ZERO,
+            last_report: U256::ZERO,
+            current_debt,
+            max_debt,
+        });
+    }
+
+    let total_initial_amount = initial_datas.iter().map(|position| position.debt).sum();
+    let headroom = initial_datas
+        .iter()
+        .zip(&strategy_datas)
+        .map(|(position, strategy)| strategy.max_debt.saturating_sub(position.debt))
+        .sum();
+    let rate_models = if rng.gen_bool(0.5) {
+        (0..strategy_count)
+            .map(|_| random_rate_model(rng))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    AllocationInput {
+        chunk_count: rng.gen_range(1..=200),
+        total_initial_amount,
+        // Sometimes more than the strategies can take, leaving the rest idle.
+        total_available_amount: if rng.gen_bool(0.2) {
+            total_initial_amount + headroom + amount_up_to(rng, headroom)
+        } else {
+            total_initial_amount + amount_up_to(rng, headroom)
+        },
+        initial_datas,
+        strategy_datas,
+        sturdy_datas,
+        objective: if rng.gen_bool(0.5) {
+            Objective::TotalInterest
+        } else {
+            Objective::MarginalRate
+        },
+        constraints: Constraints {
+            limit_withdrawals: rng.gen_bool(0.5),
+            ..Default::default()
+        },
+        // Disabled so that success only depends on the objective.
+        cost_model: CostModel::default(),
+        vault: Address::repeat_byte(0x07),
+        snapshot_timestamp: U256::from(CUR_TIMESTAMP),
+        rate_models,
+        rate_horizon: if rng.gen_bool(0.5) {
+            U256::from(rng.gen_range(1..=30 * 86_400u64))
+        } else {
+            U256::ZERO
+        },
+    }
+}
+
+/// Check the invariants of a computed allocation.
+fn check_invariants(input: &AllocationInput, result: &VaultResult) -> Result<()> {
+    if result.errorCode != 0 {
+        ensure!(!result.isSuccess, "failed allocation reported as success");
+        ensure!(
+            result.allocations.is_empty() && result.reports.is_empty(),
+            "failed allocation has debts"
+        );
+        return Ok(());
+    }
+
+    // The proposal is only committed on success, so check the native one.
+    let proposal =
+        get_optimal_allocation(input).map_err(|err| anyhow!("Native allocation failed: {err}"))?;
+    if !proposal.is_empty() {
+        ensure!(
+            proposal.len() == input.initial_datas.len(),
+            "proposal does not cover every strategy"
+        );
+        let total_debt: U256 = proposal.iter().map(|position| position.debt).sum();
+        ensure!(
+            total_debt + result.unallocatedAmount == input.total_available_amount,
+            "total debt {total_debt} and unallocated {} differ from the available {}",
+            result.unallocatedAmount,
+            input.total_available_amount
+        );
+    }
+    for position in &proposal {
+        let index = input
+            .initial_datas
+            .iter()
+            .position(|initial| initial.strategy == position.strategy)
+            .context("proposal names an unknown strategy")?;
+        let cap = input.strategy_datas[index]
+            .max_debt
+            .max(input.initial_datas[index].debt);
+        ensure!(
+            position.debt <= cap,
+            "strategy {} gets {} above its max debt {cap}",
+            position.strategy,
+            position.debt
+        );
+        ensure!(
+            result.unallocatedAmount == U256::ZERO || position.debt == cap,
+            "{} left idle while strategy {} is below its max debt",
+            result.unallocatedAmount,
+            position.strategy
+        );
+    }
+
+    ensure!(
+        result.reports.len() == input.initial_datas.len(),
+        "reports do not cover every strategy"
+    );
+    for (report, (initial, strategy)) in result
+        .reports
+        .iter()
+        .zip(input.initial_datas.iter().zip(&input.strategy_datas))
+    {
+        let target_debt = proposal
+            .iter()
+            .find(|position| position.strategy == initial.strategy)
+            .map_or(strategy.current_debt, |position| position.debt);
+        ensure!(
+            report.strategy == initial.strategy
+                && report.currentDebt == strategy.current_debt
+                && report.targetDebt == target_debt,
+            "report of strategy {} differs from the native proposal",
+            initial.strategy
+        );
+    }
+
+    let performance = &result.performance;
+    let improves = match input.objective {
+        Objective::TotalInterest => performance.newInterest > performance.curInterest,
+        Objective::MarginalRate => performance.newAPR > performance.curAPR,
+    };
+    ensure!(
+        result.isSuccess == improves,
+        "success flag {} inconsistent with APR {} -> {} and interest {} -> {}",
+        result.isSuccess,
+        performance.curAPR,
+        performance.newAPR,
+        performance.curInterest,
+        performance.newInterest
+    );
+    if result.isSuccess {
+        ensure!(
+            result.allocations.len() == proposal.len(),
+            "committed allocation differs from the native proposal"
+        );
+        for (committed, native) in result.allocations.iter().zip(&proposal) {
+            ensure!(
+                committed.strategy == native.strategy && committed.debt == native.debt,
+                "committed allocation differs from the native proposal"
+            );
+        }
+        check_actions(input, result)?;
+    } else {
+        ensure!(
+            result.allocations.is_empty() && result.actions.is_empty(),
+            "unsuccessful allocation has debts"
+        );
+    }
+    Ok(())
+}
+
+/// Check that the committed actions, applied in order, reach the committed
+/// debts without ever spending more than the vault holds.
+fn check_actions(input: &AllocationInput, result: &VaultResult) -> Result<()> {
+    let mut debts: Vec<U256> = input
+        .strategy_datas
+        .iter()
+        .map(|strategy| strategy.current_debt)
+        .collect();
+    let mut funds = input
+        .total_available_amount
+        .saturating_sub(debts.iter().copied().sum());
+    for action in &result.actions {
+        let index = input
+            .initial_datas
+            .iter()
+            .position(|initial| initial.strategy == action.strategy)
+            .context("action names an unknown strategy")?;
+        ensure!(action.amount > U256::ZERO, "empty action");
+        if action.direction == Direction::Withdraw as u8 {
+            debts[index] -= action.amount;
+            funds += action.amount;
+        } else {
+            ensure!(
+                action.amount <= funds,
+                "deposit of {} into {} is not funded",
+                action.amount,
+                action.strategy
+            );
+            debts[index] += action.amount;
+            funds -= action.amount;
+        }
+    }
+    for allocation in &result.allocations {
+        let index = input
+            .initial_datas
+            .iter()
+            .position(|initial| initial.strategy == allocation.strategy)
+            .context("allocation names an unknown strategy")?;
+        ensure!(
+            debts[index] == allocation.debt,
+            "actions leave {} at {} instead of {}",
+            allocation.strategy,
+            debts[index],
+            allocation.debt
+        );
+    }
+    Ok(())
+}
+
+/// Seed of `ALLOCATION_TEST_SEED`, or [DEFAULT_SEED] when unset.
+///
+/// The seed is printed so that the test harness shows it, with the rest of the
+/// captured output, when the test fails.
+fn test_seed() -> Result<u64> {
+    let seed = match env::var("ALLOCATION_TEST_SEED") {
+        Ok(seed) => seed.parse().context("Invalid ALLOCATION_TEST_SEED")?,
+        Err(_) => DEFAULT_SEED,
+    };
+    println!("ALLOCATION_TEST_SEED={seed}");
+    Ok(seed)
+}
+
+#[test]
+fn guest_matches_native_allocation() -> Result<()> {
+    let seed = test_seed()?;
+    let cases = match env::var("ALLOCATION_TEST_CASES") {
+        Ok(cases) => cases.parse().context("Invalid ALLOCATION_TEST_CASES")?,
+        Err(_) => DEFAULT_CASES,
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for case in 0..cases {
+        let input = random_input(&mut rng);
+        let encoded = encode_input(std::slice::from_ref(&input));
+        let context = || format!("case {case} of seed {seed}: {input:?}");
+
+        let journal = execute_locally(&encoded).with_context(context)?;
+        let expected = encode_journal(&evaluate(&encoded));
+        ensure!(
+            journal == expected,
+            "guest journal differs from the native one in {}",
+            context()
+        );
+        let journal =
+            decode_journal(&journal).map_err(|err| anyhow!("Failed to decode journal: {err}"))?;
+        ensure!(journal.results.len() == 1, "expected a single vault result");
+        check_invariants(&input, &journal.results[0]).with_context(context)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn guest_rejects_malformed_input() -> Result<()> {
+    let journal = execute_locally(&[0xff; 7])?;
+    ensure!(journal == encode_journal(&evaluate(&[0xff; 7])));
+
+    let journal =
+        decode_journal(&journal).map_err(|err| anyhow!("Failed to decode journal: {err}"))?;
+    ensure!(journal.results.len() == 1);
+    ensure!(journal.results[0].errorCode != 0 && !journal.results[0].isSuccess);
+    Ok(())
+}
+
+#[test]
+fn guest_rejects_invalid_input() -> Result<()> {
+    let seed = test_seed()?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    // At least two strategies, so that one can be listed twice.
+    let input = loop {
+        let input = random_input(&mut rng);
+        if input.initial_datas.len() >= 2 {
+            break input;
+        }
+    };
+
+    type Corruption = fn(&mut AllocationInput);
+    let rejections: [(AllocationError, Corruption); 7] = [
+        (AllocationError::LengthMismatch, |input| {
+            input.sturdy_datas.pop();
+        }),
+        (AllocationError::DuplicateStrategy, |input| {
+            input.initial_datas[1].strategy = input.initial_datas[0].strategy;
+        }),
+        (AllocationError::AvailableBelowInitial, |input| {
+            input.total_initial_amount = input.total_available_amount + U256::from(1);
+        }),
+        (AllocationError::ZeroChunkCount, |input| {
+            input.chunk_count = 0
+        }),
+        (AllocationError::InvalidTargetUtilization, |input| {
+            input.sturdy_datas[0].max_target_util = input.sturdy_datas[0].util_prec;
+        }),
+        (AllocationError::TimestampBeforeLastUpdate, |input| {
+            input.sturdy_datas[0].last_timestamp =
+                input.sturdy_datas[0].cur_timestamp + U256::from(1);
+        }),
+        (AllocationError::InvalidProtocolFee, |input| {
+            input.sturdy_datas[0].fee_to_protocol_rate = U256::from(FEE_PRECISION + 1);
+        }),
+    ];
+    for (error, corrupt) in rejections {
+        let mut invalid = input.clone();
+        corrupt(&mut invalid);
+        let encoded = encode_input(std::slice::from_ref(&invalid));
+        let context = || format!("{error:?} of seed {seed}: {invalid:?}");
+
+        let journal = execute_locally(&encoded).with_context(context)?;
+        ensure!(
+            journal == encode_journal(&evaluate(&encoded)),
+            "guest journal differs from the native one for {}",
+            context()
+        );
+        let journal =
+            decode_journal(&journal).map_err(|err| anyhow!("Failed to decode journal: {err}"))?;
+        let result = &journal.results[0];
+        ensure!(
+            result.errorCode == error.code() && !result.isSuccess,
+            "expected error code {} but got {} for {}",
+            error.code(),
+            result.errorCode,
+            context()
+        );
+        ensure!(result.snapshot.vault == invalid.vault);
+    }
+    Ok(())
+}
+
+#[test]
+fn guest_allocates_vaults_in_order() -> Result<()> {
+    let seed = test_seed()?;
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // The second vault shares the pairs of the strategies it has in common
+    // with the first one.
+    let first = random_input(&mut rng);
+    let mut second = random_input(&mut rng);
+    second.vault = Address::repeat_byte(0x08);
+    for (pair, shared) in second.sturdy_datas.iter_mut().zip(&first.sturdy_datas) {
+        *pair = *shared;
+    }
+    let encoded = encode_input(&[first.clone(), second.clone()]);
+    let context = || format!("seed {seed}: {first:?} then {second:?}");
+
+    let journal = execute_locally(&encoded).with_context(context)?;
+    ensure!(
+        journal == encode_journal(&evaluate(&encoded)),
+        "guest journal differs from the native one for {}",
+        context()
+    );
+    let journal =
+        decode_journal(&journal).map_err(|err| anyhow!("Failed to decode journal: {err}"))?;
+    ensure!(journal.results.len() == 2, "expected one result per vault");
+    let (first_result, second_result) = (&journal.results[0], &journal.results[1]);
+    ensure!(first_result.snapshot.vault == first.vault);
+    ensure!(second_result.snapshot.vault == second.vault);
+    check_invariants(&first, first_result).with_context(context)?;
+
+    // The second vault starts from the utilization the first one leaves.
+    if first_result.errorCode == 0 && second_result.errorCode == 0 {
+        for (first_report, second_report) in first_result.reports.iter().zip(&second_result.reports)
+        {
+            let expected = if first_result.isSuccess {
+                first_report.projectedUtilization
+            } else {
+                first_report.currentUtilization
+            };
+            ensure!(
+                second_report.currentUtilization == expected,
+                "strategy {} does not see the debt moved by the first vault in {}",
+                second_report.strategy,
+                context()
+            );
+        }
+    }
+    Ok(())
+}
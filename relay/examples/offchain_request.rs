@@ -17,7 +17,10 @@ use std::env;
 use anyhow::Context;
 use bonsai_ethereum_relay::sdk::client::{CallbackRequest, Client};
 use clap::Parser;
-use ethers::{types::{Address}, utils::id};
+use ethers::{
+    types::Address,
+    utils::{id, keccak256},
+};
 use methods::OPTIMAL_ALLOCATION_ID;
 use risc0_zkvm::sha::Digest;
 
@@ -66,12 +69,16 @@ async fn main() -> anyhow::Result<()> {
 
     let input = (0..args.abi_encoded_data.len())
         .step_by(2)
-        .map(|i| u8::from_str_radix(&args.abi_encoded_data[i..i +  2],  16))
+        .map(|i| u8::from_str_radix(&args.abi_encoded_data[i..i + 2], 16))
         .collect::<Result<Vec<u8>, _>>()
         .expect("Failed to convert hex string to Vec<u8>");
 
+    // The contract only accepts results for inputs registered with
+    // `expectResult`.
+    println!("Input hash: 0x{}", hex::encode(keccak256(&input)));
+
     // Set the function selector of the callback function.
-    let function_signature = "onResult((address,uint256)[],uint256,uint256,bool)";
+    let function_signature = "onResult(((address,uint256)[],(address,uint8,uint256)[],(uint256,uint256,uint256,uint256),(uint256[],uint256,uint256,bool),int256,uint256,bool,uint8,(address,bytes32,uint256),(address,uint256,uint256,uint256,uint256,uint256,uint256,uint256,uint256,uint256)[])[])";
     let function_selector = id(function_signature);
 
     // Create a CallbackRequest for your contract
@@ -81,7 +88,7 @@ async fn main() -> anyhow::Result<()> {
         function_selector,
         gas_limit: 3000000,
         image_id: Digest::from(OPTIMAL_ALLOCATION_ID).into(),
-        input
+        input,
     };
 
     // Send the callback request to the Bonsai Relay.
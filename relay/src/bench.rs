@@ -0,0 +1,308 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cycle benchmark of the guests over a matrix of input sizes.
+//!
+//! Every scenario is executed locally and its cycles and segments are checked
+//! against a committed budget file, keyed by guest name then scenario name:
+//!
+//! ```toml
+//! [OPTIMAL_ALLOCATION.strategies-4-chunks-100]
+//! cycles = 2306867
+//! segments = 3
+//! ```
+//!
+//! Scenarios missing from the file fail the run like those over budget, so
+//! that a new scenario cannot go unchecked until `--update-budget` records it.
+
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use allocation::{
+    abi::encode_input, AllocationInput, Constraints, CostModel, Objective, Position,
+    StrategyParams, SturdyDataParams,
+};
+use alloy_primitives::{Address, U256};
+use anyhow::{bail, Context, Result};
+use ethers::abi::Token;
+use risc0_build::GuestListEntry;
+use risc0_zkvm::{default_executor, ExecutorEnv};
+use serde::{Deserialize, Serialize};
+
+/// Fibonacci indices benchmarked. The guest overflows past 370.
+const FIBONACCI_INDICES: [u64; 3] = [10, 100, 300];
+
+/// Strategy counts benchmarked for the optimal allocation.
+const STRATEGY_COUNTS: [usize; 4] = [1, 2, 4, 8];
+
+/// Chunk counts benchmarked for the optimal allocation.
+const CHUNK_COUNTS: [u64; 3] = [10, 100, 1000];
+
+/// Margin added on top of the measured cycles when budgets are updated, so
+/// that small changes do not fail the benchmark.
+const BUDGET_HEADROOM_PERCENT: u64 = 10;
+
+/// Comment kept at the top of the budget file when it is rewritten.
+const BUDGET_FILE_HEADER: &str = "\
+# Cycle and segment budgets of the guest benchmark, keyed by guest name then
+# scenario name. Checked by `bonsai-ethereum-relay-cli bench` and rewritten by
+# `bonsai-ethereum-relay-cli bench --update-budget`.
+
+";
+
+/// Guest input benchmarked.
+pub struct Scenario {
+    pub name: String,
+    pub input: Vec<u8>,
+}
+
+/// Cost of executing a scenario.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Budget {
+    /// Total cycles, including the padding of each segment to a power of two.
+    pub cycles: u64,
+    pub segments: u64,
+}
+
+/// Budgets of every guest, keyed by guest name then scenario name.
+pub type Budgets = BTreeMap<String, BTreeMap<String, Budget>>;
+
+/// Measured cost of a scenario and how it compares to its budget.
+pub struct BenchResult {
+    pub guest: String,
+    pub scenario: String,
+    pub measured: Budget,
+    pub budget: Option<Budget>,
+}
+
+impl BenchResult {
+    /// Whether the scenario exceeds its budget. Scenarios without a budget
+    /// always do.
+    pub fn is_over_budget(&self) -> bool {
+        self.budget.map_or(true, |budget| {
+            self.measured.cycles > budget.cycles || self.measured.segments > budget.segments
+        })
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} cycles {} segments {}",
+            self.guest, self.scenario, self.measured.cycles, self.measured.segments
+        )?;
+        match self.budget {
+            Some(budget) => write!(
+                f,
+                " budget {}/{}{}",
+                budget.cycles,
+                budget.segments,
+                if self.is_over_budget() {
+                    " OVER BUDGET"
+                } else {
+                    ""
+                }
+            ),
+            None => f.write_str(" NO BUDGET"),
+        }
+    }
+}
+
+/// Read the budgets from a TOML file.
+pub fn load_budgets(path: &Path) -> Result<Budgets> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read budget file {}", path.display()))?;
+    toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse budget file {}", path.display()))
+}
+
+/// Record the measured cost of `results`, with [BUDGET_HEADROOM_PERCENT] of
+/// margin on the cycles, as their new budgets.
+pub fn update_budgets(budgets: &mut Budgets, results: &[BenchResult]) {
+    for result in results {
+        budgets.entry(result.guest.clone()).or_default().insert(
+            result.scenario.clone(),
+            Budget {
+                cycles: result.measured.cycles * (100 + BUDGET_HEADROOM_PERCENT) / 100,
+                segments: result.measured.segments,
+            },
+        );
+    }
+}
+
+/// Write the budgets to a TOML file.
+pub fn save_budgets(path: &Path, budgets: &Budgets) -> Result<()> {
+    let contents = toml::to_string(budgets).context("Failed to encode budgets")?;
+    std::fs::write(path, format!("{BUDGET_FILE_HEADER}{contents}"))
+        .with_context(|| format!("Failed to write budget file {}", path.display()))
+}
+
+/// Inputs benchmarked for `guest`.
+pub fn scenarios(guest: &GuestListEntry) -> Result<Vec<Scenario>> {
+    Ok(match guest.name {
+        "FIBONACCI" => FIBONACCI_INDICES
+            .iter()
+            .map(|n| Scenario {
+                name: format!("n-{n}"),
+                input: ethers::abi::encode(&[Token::Uint((*n).into())]),
+            })
+            .collect(),
+        "OPTIMAL_ALLOCATION" => STRATEGY_COUNTS
+            .iter()
+            .flat_map(|strategy_count| {
+                CHUNK_COUNTS.iter().map(move |chunk_count| Scenario {
+                    name: format!("strategies-{strategy_count}-chunks-{chunk_count}"),
+                    input: encode_input(&[allocation_input(*strategy_count, *chunk_count)]),
+                })
+            })
+            .collect(),
+        name => bail!("No benchmark scenarios defined for guest {name}"),
+    })
+}
+
+/// Execute `input` on `elf` and measure its cost.
+pub fn measure(elf: &[u8], input: &[u8]) -> Result<Budget> {
+    let env = ExecutorEnv::builder()
+        .write_slice(input)
+        .build()
+        .context("Failed to build exec env")?;
+    let session = default_executor()
+        .execute_elf(env, elf)
+        .context("Failed to run executor")?;
+    Ok(Budget {
+        cycles: session.get_cycles()? as u64,
+        segments: session.segments.len() as u64,
+    })
+}
+
+/// Run every scenario of `guests` and compare it with `budgets`.
+pub fn run(guests: &[GuestListEntry], budgets: &Budgets) -> Result<Vec<BenchResult>> {
+    let mut results = Vec::new();
+    for guest in guests {
+        for scenario in scenarios(guest)? {
+            let measured = measure(guest.elf, &scenario.input)
+                .with_context(|| format!("Failed to execute {} {}", guest.name, scenario.name))?;
+            let budget = budgets
+                .get(guest.name)
+                .and_then(|scenarios| scenarios.get(&scenario.name))
+                .copied();
+            results.push(BenchResult {
+                guest: guest.name.to_string(),
+                scenario: scenario.name,
+                measured,
+                budget,
+            });
+        }
+    }
+    Ok(results)
+}
+
+/// Vault of `strategy_count` strategies sharing 3000 assets each, cycling
+/// through a few pair states read from mainnet.
+fn allocation_input(strategy_count: usize, chunk_count: u64) -> AllocationInput {
+    let e18 = U256::from(1_000_000_000_000_000_000u64);
+    let pairs: [(u64, u64, u64, u128, u128); 4] = [
+        (
+            1_694_820_803,
+            162_996_627,
+            1_582_470_460,
+            1_000_515_508_599_392_284_665_549,
+            14_607_601_723_157_079_315_388,
+        ),
+        (
+            1_697_698_655,
+            918_533_958,
+            5_894_455_579,
+            1_007_893_653_006_093_263_158_516,
+            646_952_490_709_056_909_560_829,
+        ),
+        (
+            1_697_564_051,
+            176_565_000,
+            1_582_470_460,
+            1_250_761_314_812_572_567_422_124,
+            65_381_747_702_435_868_428_080,
+        ),
+        (
+            1_697_096_315,
+            334_268_038,
+            1_582_470_460,
+            100_187_460_695_884_368_772_125,
+            92_172_460_683_966_387_859_446,
+        ),
+    ];
+
+    AllocationInput {
+        chunk_count,
+        total_initial_amount: U256::ZERO,
+        total_available_amount: U256::from(3000 * strategy_count) * e18,
+        initial_datas: (0..strategy_count)
+            .map(|j| Position {
+                strategy: Address::repeat_byte(j as u8 + 1),
+                debt: U256::ZERO,
+            })
+            .collect(),
+        strategy_datas: (0..strategy_count)
+            .map(|_| StrategyParams {
+                activation: U256::ZERO,
+                last_report: U256::ZERO,
+                current_debt: U256::ZERO,
+                max_debt: U256::from(6000) * e18,
+            })
+            .collect(),
+        sturdy_datas: pairs
+            .iter()
+            .cycle()
+            .take(strategy_count)
+            .map(
+                |(
+                    last_timestamp,
+                    rate_per_sec,
+                    full_utilization_rate,
+                    total_asset,
+                    total_borrow,
+                )| {
+                    SturdyDataParams {
+                        cur_timestamp: U256::from(1_697_739_119),
+                        last_timestamp: U256::from(*last_timestamp),
+                        rate_per_sec: U256::from(*rate_per_sec),
+                        full_utilization_rate: U256::from(*full_utilization_rate),
+                        total_asset: U256::from(*total_asset),
+                        total_borrow: U256::from(*total_borrow),
+                        util_prec: U256::from(100_000),
+                        min_target_util: U256::from(75_000),
+                        max_target_util: U256::from(85_000),
+                        vertex_utilization: U256::from(87_500),
+                        min_full_util_rate: U256::from(1_582_470_460),
+                        max_full_util_rate: U256::from(3_164_940_920_000u64),
+                        zero_util_rate: U256::from(158_247_046),
+                        rate_half_life: U256::from(172_800),
+                        vertex_rate_percent: U256::from(200_000_000_000_000_000u64),
+                        rate_prec: e18,
+                        is_interest_paused: false,
+                        fee_to_protocol_rate: U256::ZERO,
+                    }
+                },
+            )
+            .collect(),
+        objective: Objective::TotalInterest,
+        constraints: Constraints::default(),
+        cost_model: CostModel::default(),
+        vault: Address::ZERO,
+        snapshot_timestamp: U256::from(1_697_739_119),
+        rate_models: vec![],
+        rate_horizon: U256::ZERO,
+    }
+}
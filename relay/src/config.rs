@@ -0,0 +1,141 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Configuration file for relaying callbacks on several chains from one
+//! process.
+//!
+//! ```toml
+//! [[chains]]
+//! name = "mainnet"
+//! eth_node = "wss://eth-mainnet.g.alchemy.com/v2/KEY"
+//! eth_chain_id = 1
+//! relay_address = "0x..."
+//! private_key_env = "MAINNET_RELAYER_KEY"
+//! rest_api_port = 8080
+//!
+//! [chains.tx_policy]
+//! tx_retries = 3
+//! tx_confirmation_timeout_secs = 60
+//!
+//! [[chains]]
+//! name = "sepolia"
+//! eth_node = "wss://eth-sepolia.g.alchemy.com/v2/KEY"
+//! eth_chain_id = 11155111
+//! relay_address = "0x..."
+//! private_key_env = "SEPOLIA_RELAYER_KEY"
+//! ```
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{bail, Context, Result};
+use ethers::types::Address;
+use serde::Deserialize;
+
+use crate::tx::TxPolicy;
+
+/// Chains served by a single relay process.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelayConfig {
+    pub chains: Vec<ChainTarget>,
+}
+
+/// Relay contract on one chain, with the node and wallet used to reach it.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainTarget {
+    /// Name used in logs and metrics. Defaults to the chain ID.
+    pub name: Option<String>,
+    /// Ethereum Node endpoint.
+    pub eth_node: String,
+    /// Ethereum chain ID.
+    pub eth_chain_id: u64,
+    /// Bonsai Relay contract address on this chain.
+    pub relay_address: Address,
+    /// Wallet Key Identifier, as a hex private key or an AWS KMS key
+    /// identifier. Prefer `private_key_env` to keep keys out of the file.
+    pub private_key: Option<String>,
+    /// Environment variable holding the Wallet Key Identifier.
+    pub private_key_env: Option<String>,
+    /// Port of the REST API accepting off-chain callback requests for this
+    /// chain. The REST API is disabled when unset.
+    pub rest_api_port: Option<u16>,
+    /// Transaction policy for this chain. Defaults to the command line
+    /// options. Only the retry count and confirmation timeout may be set, see
+    /// [TxPolicy::relayer_settings].
+    pub tx_policy: Option<TxPolicy>,
+}
+
+impl RelayConfig {
+    /// Read and validate a TOML configuration file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read relay config {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse relay config {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.chains.is_empty() {
+            bail!("Relay config must list at least one chain");
+        }
+
+        let mut targets = HashSet::new();
+        let mut ports = HashSet::new();
+        for chain in &self.chains {
+            if !targets.insert((chain.eth_chain_id, chain.relay_address)) {
+                bail!(
+                    "Relay contract {:?} is listed twice for chain {}",
+                    chain.relay_address,
+                    chain.eth_chain_id
+                );
+            }
+            if let Some(port) = chain.rest_api_port {
+                if !ports.insert(port) {
+                    bail!("REST API port {port} is used by more than one chain");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChainTarget {
+    /// Name used in logs and metrics.
+    pub fn label(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.eth_chain_id.to_string())
+    }
+
+    /// Resolve the Wallet Key Identifier from the file or the environment.
+    pub fn wallet_key(&self) -> Result<String> {
+        match (&self.private_key, &self.private_key_env) {
+            (Some(private_key), None) => Ok(private_key.clone()),
+            (None, Some(var)) => std::env::var(var).with_context(|| {
+                format!(
+                    "Environment variable {var} for chain {} is not set",
+                    self.label()
+                )
+            }),
+            (Some(_), Some(_)) => bail!(
+                "Chain {} sets both private_key and private_key_env",
+                self.label()
+            ),
+            (None, None) => bail!("Chain {} has no private key", self.label()),
+        }
+    }
+}
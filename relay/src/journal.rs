@@ -0,0 +1,115 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Human readable view of an `OPTIMAL_ALLOCATION` journal.
+
+use std::fmt;
+
+use allocation::{
+    abi::{self, decode_journal, OptimalAllocationJournal},
+    StrategyReport, APR_PRECISION,
+};
+use alloy_primitives::U256;
+use anyhow::{anyhow, Context, Result};
+
+use crate::simulator::{error_code, percent, write_report};
+
+/// Journal committed by the `OPTIMAL_ALLOCATION` guest, printed vault by vault
+/// as a summary followed by the rebalance actions and the report of every
+/// strategy.
+pub struct DecodedJournal {
+    pub journal: OptimalAllocationJournal,
+    /// Reports of every vault, in journal order.
+    pub reports: Vec<Vec<StrategyReport>>,
+}
+
+impl DecodedJournal {
+    /// Decode a hex encoded journal, with or without its `0x` prefix.
+    pub fn from_hex(journal: &str) -> Result<Self> {
+        let bytes = hex::decode(journal.trim_start_matches("0x"))
+            .context("Failed to decode journal hex")?;
+        let journal = decode_journal(&bytes)
+            .map_err(|err| anyhow!("Failed to decode optimal allocation journal: {err}"))?;
+
+        let reports = journal
+            .results
+            .iter()
+            .map(|result| result.reports.iter().map(strategy_report).collect())
+            .collect::<Result<_>>()?;
+        Ok(Self { journal, reports })
+    }
+}
+
+impl fmt::Display for DecodedJournal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let apr_precision = U256::from(APR_PRECISION);
+        for (result, reports) in self.journal.results.iter().zip(&self.reports) {
+            let performance = &result.performance;
+            writeln!(
+                f,
+                "vault {} input {} at {} success {} error code {}",
+                result.snapshot.vault,
+                result.snapshot.inputHash,
+                result.snapshot.timestamp,
+                result.isSuccess,
+                error_code(result.errorCode)
+            )?;
+            writeln!(
+                f,
+                "  vault APR {} -> {} interest {} -> {} net gain {} unallocated {}",
+                percent(performance.curAPR, apr_precision),
+                percent(performance.newAPR, apr_precision),
+                performance.curInterest,
+                performance.newInterest,
+                result.netGain,
+                result.unallocatedAmount
+            )?;
+
+            for (index, action) in result.actions.iter().enumerate() {
+                let direction = match action.direction {
+                    0 => "withdraw",
+                    1 => "deposit",
+                    _ => "unknown",
+                };
+                writeln!(
+                    f,
+                    "  action {index}: {direction} {} {}",
+                    action.amount, action.strategy
+                )?;
+            }
+            for report in reports {
+                write_report(f, report)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Native view of a committed strategy report.
+fn strategy_report(report: &abi::StrategyReport) -> Result<StrategyReport> {
+    Ok(StrategyReport {
+        strategy: report.strategy,
+        current_debt: report.currentDebt,
+        target_debt: report.targetDebt,
+        util_prec: report.utilPrec,
+        current_utilization: report.currentUtilization,
+        projected_utilization: report.projectedUtilization,
+        current_rate_per_sec: u64::try_from(report.currentRatePerSec)
+            .context("Current rate per second exceeds u64")?,
+        projected_rate_per_sec: u64::try_from(report.projectedRatePerSec)
+            .context("Projected rate per second exceeds u64")?,
+        current_apr: report.currentAPR,
+        projected_apr: report.projectedAPR,
+    })
+}
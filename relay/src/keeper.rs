@@ -0,0 +1,344 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keeper that decides when an on-chain optimal allocation request is worth
+//! paying for.
+//!
+//! On every tick the keeper asks `ZKOptimalAllocation` for the guest input
+//! matching the current vault state, executes `OPTIMAL_ALLOCATION` locally and
+//! only calls `startOptimalAllocation` when the simulated APR gain beats the
+//! configured threshold, the cooldown has elapsed and the transaction fits in
+//! the gas budget.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use allocation::abi::decode_journal;
+use anyhow::{anyhow, bail, Context, Result};
+use ethers::{
+    contract::abigen,
+    middleware::SignerMiddleware,
+    providers::{Middleware, Provider, Ws},
+    signers::{LocalWallet, Signer},
+    types::{Address, TxHash, I256, U256},
+};
+use methods::OPTIMAL_ALLOCATION_ELF;
+use tracing::{error, info, info_span, Instrument};
+
+use crate::{
+    execute_locally, metrics,
+    simulator::error_code,
+    tx::{send_with_policy, TxPolicy},
+    Output,
+};
+
+abigen!(
+    ZkOptimalAllocation,
+    r#"[
+        struct StrategyAllocation { address strategy; uint256 debt; }
+        function optimalAllocationInput(address vault, uint256 chunkCount, uint256 totalInitialAmount, StrategyAllocation[] initialDatas) external view returns (bytes)
+        function startOptimalAllocation(address vault, uint256 chunkCount, uint256 totalInitialAmount, StrategyAllocation[] initialDatas) external
+    ]"#
+);
+
+/// Middleware stack used by the keeper to read state and send transactions.
+pub type KeeperClient = SignerMiddleware<Provider<Ws>, LocalWallet>;
+
+/// Connect to an Ethereum node and attach a local signing wallet.
+pub async fn connect(
+    eth_node: &str,
+    eth_chain_id: u64,
+    private_key: &str,
+) -> Result<Arc<KeeperClient>> {
+    let provider = Provider::<Ws>::connect(eth_node)
+        .await
+        .context("Failed to connect to Ethereum node")?;
+    let wallet = private_key
+        .parse::<LocalWallet>()
+        .context("Failed to parse keeper private key")?
+        .with_chain_id(eth_chain_id);
+    Ok(Arc::new(SignerMiddleware::new(provider, wallet)))
+}
+
+/// Settings controlling when the keeper requests a reallocation.
+#[derive(Clone, Debug)]
+pub struct KeeperConfig {
+    /// Address of the `ZKOptimalAllocation` contract.
+    pub allocation_address: Address,
+    /// Vault whose strategies are reallocated.
+    pub vault: Address,
+    /// Strategies considered by the allocation, in vault order.
+    pub strategies: Vec<Address>,
+    /// Chunk count passed to the guest, bounding the solver bisection steps.
+    /// The guest always allows at least 64 steps.
+    pub chunk_count: U256,
+    /// Minimum `new_apr - current_apr`, in the guest's 1e18 APR precision,
+    /// required before a request is submitted.
+    pub min_apr_gain: U256,
+    /// Minimum time between two submitted requests.
+    pub cooldown: Duration,
+    /// Time between two evaluations of the vault state.
+    pub poll_interval: Duration,
+    /// Maximum cost in wei of a single request, at the max fee per gas it is
+    /// first sent with.
+    pub gas_budget: U256,
+    /// Pricing, retries and replacement of the request transactions.
+    pub tx_policy: TxPolicy,
+}
+
+/// Result of the `OPTIMAL_ALLOCATION` guest, decoded from its journal.
+#[derive(Clone, Debug)]
+pub struct Proposal {
+    pub allocations: Vec<(Address, U256)>,
+    pub new_apr: U256,
+    pub current_apr: U256,
+    /// Yearly interest of the proposal, in asset units.
+    pub new_interest: U256,
+    /// Yearly interest of the current debts, in asset units.
+    pub current_interest: U256,
+    /// Interest gained over the holding horizon minus the gas cost, in asset
+    /// base units.
+    pub net_gain: I256,
+    /// Assets the proposal leaves idle because the strategies are full.
+    pub unallocated_amount: U256,
+    pub is_success: bool,
+    /// Error code reported by the guest, zero when the allocation was
+    /// computed.
+    pub error_code: u8,
+}
+
+impl Proposal {
+    /// Decode the ABI encoded journal committed by the guest for the single
+    /// vault the keeper requests.
+    pub fn decode(journal: &[u8]) -> Result<Self> {
+        let journal = decode_journal(journal)
+            .map_err(|err| anyhow!("Failed to decode optimal allocation journal: {err}"))?;
+        let [result] = journal.results.as_slice() else {
+            bail!(
+                "Expected the result of a single vault, got {}",
+                journal.results.len()
+            );
+        };
+
+        Ok(Self {
+            allocations: result
+                .allocations
+                .iter()
+                .map(|allocation| {
+                    (
+                        Address::from_slice(allocation.strategy.as_slice()),
+                        to_u256(allocation.debt),
+                    )
+                })
+                .collect(),
+            new_apr: to_u256(result.performance.newAPR),
+            current_apr: to_u256(result.performance.curAPR),
+            new_interest: to_u256(result.performance.newInterest),
+            current_interest: to_u256(result.performance.curInterest),
+            net_gain: I256::from_raw(to_u256(result.netGain.into_raw())),
+            unallocated_amount: to_u256(result.unallocatedAmount),
+            is_success: result.isSuccess,
+            error_code: result.errorCode,
+        })
+    }
+
+    /// APR improvement of the proposal over the current allocation.
+    pub fn apr_gain(&self) -> U256 {
+        self.new_apr.saturating_sub(self.current_apr)
+    }
+}
+
+/// Outcome of a single keeper evaluation.
+#[derive(Debug)]
+pub enum Decision {
+    /// A request was submitted less than `cooldown` ago.
+    Cooldown { remaining: Duration },
+    /// The guest did not find an allocation beating the threshold.
+    BelowThreshold { gain: U256 },
+    /// Sending the request would cost more than the gas budget.
+    OverBudget { cost: U256 },
+    /// A request was sent and mined.
+    Submitted { tx_hash: TxHash, gain: U256 },
+}
+
+impl Decision {
+    /// Short name of the decision, used as a metric label.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Decision::Cooldown { .. } => "cooldown",
+            Decision::BelowThreshold { .. } => "below_threshold",
+            Decision::OverBudget { .. } => "over_budget",
+            Decision::Submitted { .. } => "submitted",
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Cooldown { remaining } => {
+                write!(f, "in cooldown for another {}s", remaining.as_secs())
+            }
+            Decision::BelowThreshold { gain } => {
+                write!(f, "APR gain {gain} does not beat the threshold")
+            }
+            Decision::OverBudget { cost } => {
+                write!(f, "request would cost {cost} wei, above the gas budget")
+            }
+            Decision::Submitted { tx_hash, gain } => {
+                write!(
+                    f,
+                    "requested allocation with APR gain {gain} in {tx_hash:?}"
+                )
+            }
+        }
+    }
+}
+
+/// Long-running loop triggering `startOptimalAllocation` when profitable.
+pub struct Keeper {
+    contract: ZkOptimalAllocation<KeeperClient>,
+    config: KeeperConfig,
+    last_submission: Option<Instant>,
+}
+
+impl Keeper {
+    pub fn new(client: Arc<KeeperClient>, config: KeeperConfig) -> Self {
+        Self {
+            contract: ZkOptimalAllocation::new(config.allocation_address, client),
+            config,
+            last_submission: None,
+        }
+    }
+
+    /// Evaluate the vault every `poll_interval` until the process is stopped.
+    pub async fn run(mut self) -> Result<()> {
+        let mut interval = tokio::time::interval(self.config.poll_interval);
+        loop {
+            interval.tick().await;
+            let span = info_span!("keeper_tick", vault = ?self.config.vault);
+            match self.tick().instrument(span).await {
+                Ok(decision) => {
+                    metrics::KEEPER_DECISIONS
+                        .with_label_values(&[decision.label()])
+                        .inc();
+                    info!(decision = decision.label(), "{decision}");
+                }
+                Err(err) => {
+                    metrics::KEEPER_DECISIONS
+                        .with_label_values(&["error"])
+                        .inc();
+                    error!("Keeper tick failed: {err:?}");
+                }
+            }
+        }
+    }
+
+    /// Run a single evaluation, submitting a request if it is worth it.
+    pub async fn tick(&mut self) -> Result<Decision> {
+        if let Some(last_submission) = self.last_submission {
+            let elapsed = last_submission.elapsed();
+            if elapsed < self.config.cooldown {
+                return Ok(Decision::Cooldown {
+                    remaining: self.config.cooldown - elapsed,
+                });
+            }
+        }
+
+        // Every strategy starts from zero so the whole available amount is
+        // redistributed.
+        let initial_datas: Vec<StrategyAllocation> = self
+            .config
+            .strategies
+            .iter()
+            .map(|strategy| StrategyAllocation {
+                strategy: *strategy,
+                debt: U256::zero(),
+            })
+            .collect();
+
+        let input = self
+            .contract
+            .optimal_allocation_input(
+                self.config.vault,
+                self.config.chunk_count,
+                U256::zero(),
+                initial_datas.clone(),
+            )
+            .call()
+            .await
+            .context("Failed to build guest input")?;
+
+        let proposal = tokio::task::spawn_blocking(move || simulate(input.to_vec()))
+            .await
+            .context("Failed to run local execution sub-task")??;
+        if proposal.error_code != 0 {
+            bail!(
+                "Guest failed with error code {}",
+                error_code(proposal.error_code)
+            );
+        }
+        let gain = proposal.apr_gain();
+        if !proposal.is_success || gain <= self.config.min_apr_gain {
+            return Ok(Decision::BelowThreshold { gain });
+        }
+
+        let call = self.contract.start_optimal_allocation(
+            self.config.vault,
+            self.config.chunk_count,
+            U256::zero(),
+            initial_datas,
+        );
+        let gas = call
+            .estimate_gas()
+            .await
+            .context("Failed to estimate request gas")?;
+        // Price the request the way send_with_policy sends it.
+        let (estimated_max_fee, _) = self
+            .contract
+            .client()
+            .estimate_eip1559_fees(None)
+            .await
+            .context("Failed to estimate EIP-1559 fees")?;
+        let cost = gas.saturating_mul(self.config.tx_policy.max_fee(estimated_max_fee));
+        if cost > self.config.gas_budget {
+            return Ok(Decision::OverBudget { cost });
+        }
+
+        let mut tx = call.tx;
+        tx.set_gas(gas);
+        let receipt = send_with_policy(self.contract.client().as_ref(), tx, &self.config.tx_policy)
+            .await
+            .context("Failed to confirm request")?;
+        let tx_hash = receipt.transaction_hash;
+        self.last_submission = Some(Instant::now());
+
+        Ok(Decision::Submitted { tx_hash, gain })
+    }
+}
+
+fn to_u256(value: alloy_primitives::U256) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+/// Execute `OPTIMAL_ALLOCATION` on `input` and decode the resulting journal.
+pub fn simulate(input: Vec<u8>) -> Result<Proposal> {
+    match execute_locally(OPTIMAL_ALLOCATION_ELF, input)? {
+        Output::Execution { journal } => Proposal::decode(&journal.bytes),
+        Output::Bonsai { .. } => bail!("Local execution returned a Bonsai output"),
+    }
+}
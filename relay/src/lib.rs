@@ -12,14 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+pub mod bench;
+pub mod config;
+pub mod journal;
+pub mod keeper;
+pub mod metrics;
+pub mod simulator;
+pub mod tx;
+
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
 use bonsai_sdk::alpha::{responses::SnarkReceipt, Client};
+use ethers::{types::U256, utils::keccak256};
 use risc0_build::GuestListEntry;
 use risc0_zkvm::{
     default_executor, ExecutorEnv, Journal, MemoryImage, Program, Receipt, GUEST_MAX_MEM, PAGE_SIZE,
 };
+use tracing::{field, info, info_span, warn};
 
 /// Result of executing a guest image, possibly containing a proof.
 pub enum Output {
@@ -30,6 +40,9 @@ pub enum Output {
 /// Execute and prove the guest locally, on this machine, as opposed to sending
 /// the proof request to the Bonsai service.
 pub fn execute_locally(elf: &[u8], input: Vec<u8>) -> Result<Output> {
+    let span = info_span!("execution", input_hash = %input_hash(&input));
+    let _enter = span.enter();
+
     // Execute the guest program, generating the session trace needed to prove the
     // computation.
     let env = ExecutorEnv::builder()
@@ -41,6 +54,10 @@ pub fn execute_locally(elf: &[u8], input: Vec<u8>) -> Result<Output> {
         .execute_elf(env, elf)
         .with_context(|| format!("Failed to run executor {:?}", &input))?;
 
+    info!(
+        segments = session.segments.len(),
+        "Completed local execution"
+    );
     Ok(Output::Execution {
         journal: session.journal,
     })
@@ -54,11 +71,24 @@ fn get_digest(elf: &[u8]) -> Result<String> {
     Ok(hex::encode(image.compute_id()))
 }
 
+/// Hex encoded keccak256 hash identifying a guest input in logs.
+fn input_hash(input: &[u8]) -> String {
+    hex::encode(keccak256(input))
+}
+
 pub fn prove_alpha(elf: &[u8], input: Vec<u8>) -> Result<Output> {
     let client =
         Client::from_env(risc0_zkvm::VERSION).context("Failed to create client from env var")?;
 
     let img_id = get_digest(elf).context("Failed to generate elf memory image")?;
+    let span = info_span!(
+        "proof_request",
+        image_id = %img_id,
+        input_hash = %input_hash(&input),
+        session_uuid = field::Empty,
+        phase = field::Empty,
+    );
+    let _enter = span.enter();
     client.upload_img(&img_id, elf.to_vec())?;
 
     let input_id = client
@@ -68,45 +98,56 @@ pub fn prove_alpha(elf: &[u8], input: Vec<u8>) -> Result<Output> {
     let session = client
         .create_session(img_id, input_id)
         .context("Failed to create remote proving session")?;
+    let stark_started = Instant::now();
+    span.record("session_uuid", session.uuid.as_str());
+    span.record("phase", "stark");
+    info!("Created STARK proving session");
 
     // Poll and await the result of the STARK rollup proving session.
-    let _receipt: Receipt = (|| {
-        loop {
-            let res = match session.status(&client) {
-                Ok(res) => res,
-                Err(err) => {
-                    eprint!("Failed to get session status: {err}");
-                    std::thread::sleep(Duration::from_secs(POLL_INTERVAL_SEC));
-                    continue;
-                }
-            };
-            match res.status.as_str() {
-                "RUNNING" => {
-                    std::thread::sleep(Duration::from_secs(POLL_INTERVAL_SEC));
-                }
-                "SUCCEEDED" => {
-                    let receipt_buf = client
-                        .download(
-                            &res.receipt_url
-                                .context("Missing 'receipt_url' on status response")?,
-                        )
-                        .context("Failed to download receipt")?;
-                    let receipt: Receipt = bincode::deserialize(&receipt_buf)
-                        .context("Failed to deserialize SessionReceipt")?;
-                    // eprintln!("Completed STARK proof on bonsai alpha backend!");
-                    return Ok(receipt);
-                }
-                _ => {
-                    bail!(
-                        "STARK proving session exited with bad status: {}",
-                        res.status
-                    );
-                }
+    let _receipt: Receipt = (|| loop {
+        let res = match session.status(&client) {
+            Ok(res) => res,
+            Err(err) => {
+                warn!("Failed to get session status: {err}");
+                std::thread::sleep(Duration::from_secs(POLL_INTERVAL_SEC));
+                continue;
+            }
+        };
+        match res.status.as_str() {
+            "RUNNING" => {
+                std::thread::sleep(Duration::from_secs(POLL_INTERVAL_SEC));
+            }
+            "SUCCEEDED" => {
+                let receipt_buf = client
+                    .download(
+                        &res.receipt_url
+                            .context("Missing 'receipt_url' on status response")?,
+                    )
+                    .context("Failed to download receipt")?;
+                let receipt: Receipt = bincode::deserialize(&receipt_buf)
+                    .context("Failed to deserialize SessionReceipt")?;
+                info!("Completed STARK proof on bonsai alpha backend");
+                metrics::SESSION_DURATION
+                    .with_label_values(&["stark"])
+                    .observe(stark_started.elapsed().as_secs_f64());
+                return Ok(receipt);
+            }
+            _ => {
+                metrics::SESSION_FAILURES
+                    .with_label_values(&["stark", &res.status])
+                    .inc();
+                bail!(
+                    "STARK proving session exited with bad status: {}",
+                    res.status
+                );
             }
         }
     })()?;
 
     let snark_session = client.create_snark(session.uuid)?;
+    let snark_started = Instant::now();
+    span.record("phase", "snark");
+    info!("Created SNARK proving session");
     let snark_receipt: SnarkReceipt = (|| loop {
         let res = snark_session.status(&client)?;
         match res.status.as_str() {
@@ -114,12 +155,18 @@ pub fn prove_alpha(elf: &[u8], input: Vec<u8>) -> Result<Output> {
                 std::thread::sleep(Duration::from_secs(POLL_INTERVAL_SEC));
             }
             "SUCCEEDED" => {
-                // eprintln!("Completed SNARK proof on bonsai alpha backend!");
+                info!("Completed SNARK proof on bonsai alpha backend");
+                metrics::SESSION_DURATION
+                    .with_label_values(&["snark"])
+                    .observe(snark_started.elapsed().as_secs_f64());
                 return res
                     .output
                     .ok_or(anyhow!("output expected to be non-empty on success"));
             }
             _ => {
+                metrics::SESSION_FAILURES
+                    .with_label_values(&["snark", &res.status])
+                    .inc();
                 bail!(
                     "SNARK proving session exited with bad status: {}",
                     res.status
@@ -172,8 +219,16 @@ pub async fn resolve_image_output(
     if dev_mode {
         execute_locally(elf, input)
     } else {
+        metrics::PROOF_REQUESTS
+            .with_label_values(&[guest_entry.name])
+            .inc();
         tokio::task::spawn_blocking(move || prove_alpha(elf, input))
             .await
             .context("Failed to run alpha sub-task")?
     }
 }
+
+/// Parse a decimal unsigned integer argument.
+pub fn parse_u256(value: &str) -> Result<U256> {
+    U256::from_dec_str(value).with_context(|| format!("invalid decimal integer: {value}"))
+}
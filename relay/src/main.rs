@@ -12,19 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::Write;
+use std::{io::Write, net::SocketAddr, panic::AssertUnwindSafe, path::PathBuf};
 
 use anyhow::Context;
 use bonsai_ethereum_relay::{tokenize_snark_receipt, EthersClientConfig, Relayer};
-use bonsai_ethereum_relay_cli::{resolve_guest_entry, resolve_image_output, Output};
+use bonsai_ethereum_relay_cli::{
+    bench::{self, Budgets},
+    config::{ChainTarget, RelayConfig},
+    journal::DecodedJournal,
+    keeper::{self, Keeper, KeeperConfig},
+    metrics, parse_u256, resolve_guest_entry, resolve_image_output,
+    simulator::{self, VaultSnapshot},
+    tx::TxPolicy,
+    Output,
+};
 use bonsai_sdk::alpha_async::{get_client_from_parts, upload_img};
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use ethers::{
     abi::{Hash, Token, Tokenizable},
-    types::Address,
+    signers::{LocalWallet, Signer},
+    types::{Address, U256},
 };
+use futures::{future::join_all, FutureExt};
 use methods::GUEST_LIST;
 use risc0_zkvm::sha::Digest;
+use tokio::task::JoinHandle;
+use tracing::error;
+use tracing_subscriber::EnvFilter;
 
 /// Index 0 private key generated by default in Anvil.
 const ANVIL_DEFAULT_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
@@ -47,9 +61,14 @@ enum Command {
     },
     /// Upload the RISC-V ELF binary to Bonsai.
     Run {
+        /// TOML file listing the chains to relay for. When set, the single
+        /// chain options below are ignored.
+        #[arg(long, env = "RELAY_CONFIG")]
+        config: Option<PathBuf>,
+
         /// Bonsai Relay contract address on Ethereum
-        #[arg(long, env)]
-        relay_address: Address,
+        #[arg(long, env, required_unless_present = "config")]
+        relay_address: Option<Address>,
 
         /// Ethereum Node endpoint.
         #[arg(long, env, default_value = "ws://localhost:8545")]
@@ -69,9 +88,117 @@ enum Command {
             default_value = ANVIL_DEFAULT_KEY
         )]
         private_key: String,
+
+        /// Transaction policy for chains that do not set one in `--config`.
+        /// The relayer only applies the retry count and confirmation timeout.
+        #[command(flatten)]
+        tx_policy: TxPolicy,
+    },
+    /// Periodically simulate the optimal allocation and request it on-chain
+    /// when the APR gain is worth it.
+    Keeper {
+        /// ZKOptimalAllocation contract address
+        #[arg(long, env)]
+        allocation_address: Address,
+
+        /// Vault whose strategies are reallocated
+        #[arg(long, env)]
+        vault: Address,
+
+        /// Comma separated list of the vault strategies to allocate across
+        #[arg(long, env, value_delimiter = ',', required = true)]
+        strategies: Vec<Address>,
+
+        /// Chunk count passed to the guest, bounding the solver bisection steps.
+        /// The guest always allows at least 64 steps.
+        #[arg(long, default_value_t = 100)]
+        chunk_count: u64,
+
+        /// Minimum APR gain, in 1e18 precision, required to request a
+        /// reallocation
+        #[arg(long, env, default_value = "0", value_parser = parse_u256)]
+        min_apr_gain: U256,
+
+        /// Minimum number of seconds between two requests
+        #[arg(long, env, default_value_t = 3600)]
+        cooldown_secs: u64,
+
+        /// Number of seconds between two evaluations of the vault
+        #[arg(long, env, default_value_t = 300)]
+        poll_interval_secs: u64,
+
+        /// Maximum cost in wei of a single request transaction
+        #[arg(long, env, value_parser = parse_u256)]
+        gas_budget: U256,
+
+        /// Ethereum Node endpoint.
+        #[arg(long, env, default_value = "ws://localhost:8545")]
+        eth_node: String,
+
+        /// Ethereum chain ID
+        #[arg(long, default_value_t = 31337)]
+        eth_chain_id: u64,
+
+        /// Private key used to send requests, as a hex string.
+        /// Defaults to the first private key of a deafult Anvil instance.
+        #[arg(
+            short,
+            long,
+            env,
+            default_value = ANVIL_DEFAULT_KEY
+        )]
+        private_key: String,
+
+        #[command(flatten)]
+        tx_policy: TxPolicy,
+    },
+    /// Simulate the optimal allocation natively on a JSON vault snapshot and
+    /// print the proposed debt changes, utilizations and APRs.
+    Simulate {
+        /// JSON file describing the vault, see the `simulator` module docs
+        snapshot: PathBuf,
+
+        /// Comma separated chunk counts to sweep over instead of the one in
+        /// the snapshot
+        #[arg(long, value_delimiter = ',')]
+        chunk_counts: Vec<u64>,
+
+        /// Comma separated total available amounts to sweep over instead of
+        /// the one in the snapshot
+        #[arg(long, value_delimiter = ',', value_parser = simulator::parse_amount)]
+        total_available: Vec<alloy_primitives::U256>,
+    },
+    /// Decode a journal committed by the optimal allocation guest and print
+    /// its actions and per-strategy utilizations, rates and APRs.
+    Decode {
+        /// Hex encoded journal bytes committed by the guest
+        journal: String,
+    },
+    /// Execute the guests over a matrix of input sizes and check their cycles
+    /// and segments against a budget file.
+    Bench {
+        /// The name of the guest binary
+        /// If not provided, all defined guests are benchmarked.
+        guest_binary: Option<String>,
+
+        /// TOML file holding the budget of every scenario
+        #[arg(long, default_value = "methods/cycle-budget.toml")]
+        budget: PathBuf,
+
+        /// Record the measured costs, plus some headroom, as the new budgets
+        /// instead of checking them
+        #[arg(long, default_value_t = false)]
+        update_budget: bool,
     },
 }
 
+/// Format of the log lines written to stderr.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LogFormat {
+    Pretty,
+    Json,
+}
+
 #[derive(Debug, Args)]
 struct GlobalOpts {
     /// Bonsai API URL
@@ -87,6 +214,16 @@ struct GlobalOpts {
     /// zkVM program and no proof is generated.
     #[arg(long, env, global = true, default_value_t = false)]
     risc0_dev_mode: bool,
+
+    /// Address on which to serve Prometheus metrics at `/metrics`.
+    /// Metrics are not served when unset.
+    #[arg(long, env, global = true)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Format of the logs written to stderr. Log levels are read from
+    /// `RUST_LOG` and default to `info`.
+    #[arg(long, env, global = true, value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
 }
 
 #[derive(Parser)]
@@ -102,8 +239,17 @@ pub struct App {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = App::parse();
+    init_tracing(args.global_opts.log_format);
     let dev_mode = args.global_opts.risc0_dev_mode;
 
+    if let Some(metrics_addr) = args.global_opts.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(metrics_addr).await {
+                error!("{err:?}");
+            }
+        });
+    }
+
     match args.command {
         Command::Query {
             guest_binary,
@@ -172,27 +318,34 @@ async fn main() -> anyhow::Result<()> {
                 .context("failed to flush stdout buffer")?;
         }
         Command::Run {
+            config,
             relay_address,
             eth_node,
             eth_chain_id,
             private_key,
+            tx_policy,
         } => {
-            let relayer = Relayer {
-                rest_api: true,
-                dev_mode,
-                rest_api_port: "8080".to_string(),
-                bonsai_api_url: args.global_opts.bonsai_api_url.clone(),
-                bonsai_api_key: args.global_opts.bonsai_api_key.clone(),
-                relay_contract_address: relay_address,
+            let chains = match config {
+                Some(path) => RelayConfig::load(&path)?.chains,
+                None => vec![ChainTarget {
+                    name: None,
+                    eth_node,
+                    eth_chain_id,
+                    relay_address: relay_address
+                        .context("--relay-address is required without --config")?,
+                    private_key: Some(private_key),
+                    private_key_env: None,
+                    rest_api_port: Some(8080),
+                    tx_policy: None,
+                }],
             };
-            let client_config = EthersClientConfig::new(
-                eth_node,
-                eth_chain_id,
-                private_key.try_into()?,
-                1,
-                std::time::Duration::from_secs(10),
-            );
-            let server_handle = tokio::spawn(relayer.run(client_config));
+
+            // Every chain shares the same Bonsai endpoint, so the images are
+            // uploaded once for all relayers.
+            let mut server_handles = Vec::with_capacity(chains.len());
+            for chain in chains {
+                server_handles.push(spawn_relayer(chain, &tx_policy, &args.global_opts).await?);
+            }
 
             // HACK: Wait 1 second to give local Bonsai a chance to start.
             std::thread::sleep(std::time::Duration::from_secs(1));
@@ -205,13 +358,169 @@ async fn main() -> anyhow::Result<()> {
             )
             .await?;
 
-            // Wait for the server to exit.
-            let _ = server_handle.await;
+            // Wait for the servers to exit.
+            join_all(server_handles).await;
+        }
+        Command::Keeper {
+            allocation_address,
+            vault,
+            strategies,
+            chunk_count,
+            min_apr_gain,
+            cooldown_secs,
+            poll_interval_secs,
+            gas_budget,
+            eth_node,
+            eth_chain_id,
+            private_key,
+            tx_policy,
+        } => {
+            let client = keeper::connect(&eth_node, eth_chain_id, &private_key).await?;
+            let config = KeeperConfig {
+                allocation_address,
+                vault,
+                strategies,
+                chunk_count: U256::from(chunk_count),
+                min_apr_gain,
+                cooldown: std::time::Duration::from_secs(cooldown_secs),
+                poll_interval: std::time::Duration::from_secs(poll_interval_secs),
+                gas_budget,
+                tx_policy,
+            };
+            Keeper::new(client, config).run().await?;
+        }
+        Command::Simulate {
+            snapshot,
+            chunk_counts,
+            total_available,
+        } => {
+            let input = VaultSnapshot::load(&snapshot)?.to_input();
+            for simulation in simulator::sweep(&input, &chunk_counts, &total_available) {
+                print!("{simulation}");
+            }
+        }
+        Command::Decode { journal } => {
+            print!("{}", DecodedJournal::from_hex(&journal)?);
+        }
+        Command::Bench {
+            guest_binary,
+            budget,
+            update_budget,
+        } => {
+            let guests = match guest_binary {
+                Some(guest_binary) => vec![resolve_guest_entry(GUEST_LIST, &guest_binary)?],
+                None => GUEST_LIST.to_vec(),
+            };
+            let mut budgets = if update_budget && !budget.exists() {
+                Budgets::new()
+            } else {
+                bench::load_budgets(&budget)?
+            };
+
+            let results = bench::run(&guests, &budgets)?;
+            for result in &results {
+                println!("{result}");
+            }
+            if update_budget {
+                bench::update_budgets(&mut budgets, &results);
+                bench::save_budgets(&budget, &budgets)?;
+            } else {
+                let over_budget = results
+                    .iter()
+                    .filter(|result| result.is_over_budget())
+                    .count();
+                if over_budget > 0 {
+                    anyhow::bail!(
+                        "{over_budget} benchmark scenarios exceed or lack their budget in {}",
+                        budget.display()
+                    );
+                }
+            }
         }
     }
     Ok(())
 }
 
+/// Install the global subscriber. Logs go to stderr because stdout is
+/// reserved for the ABI encoded output read through FFI.
+fn init_tracing(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
+
+/// Start a relayer for `chain`, returning the handle of its task.
+async fn spawn_relayer(
+    chain: ChainTarget,
+    default_tx_policy: &TxPolicy,
+    global_opts: &GlobalOpts,
+) -> anyhow::Result<JoinHandle<()>> {
+    let label = chain.label();
+    let (tx_retries, confirmation_timeout) = chain
+        .tx_policy
+        .as_ref()
+        .unwrap_or(default_tx_policy)
+        .relayer_settings()
+        .with_context(|| format!("Invalid transaction policy for chain {label}"))?;
+    let wallet_key = chain.wallet_key()?;
+    let relayer = Relayer {
+        rest_api: chain.rest_api_port.is_some(),
+        dev_mode: global_opts.risc0_dev_mode,
+        rest_api_port: chain.rest_api_port.unwrap_or_default().to_string(),
+        bonsai_api_url: global_opts.bonsai_api_url.clone(),
+        bonsai_api_key: global_opts.bonsai_api_key.clone(),
+        relay_contract_address: chain.relay_address,
+    };
+
+    // The wallet address is needed to follow callback transactions.
+    // It is only known up front for local private keys.
+    if let Ok(wallet) = wallet_key.parse::<LocalWallet>() {
+        let eth_node = chain.eth_node.clone();
+        let label = label.clone();
+        let relay_address = chain.relay_address;
+        tokio::spawn(async move {
+            metrics::track_callback_transactions(&eth_node, &label, relay_address, wallet.address())
+                .await
+        });
+    }
+
+    let client_config = EthersClientConfig::new(
+        chain.eth_node,
+        chain.eth_chain_id,
+        wallet_key.try_into()?,
+        tx_retries,
+        confirmation_timeout,
+    );
+    Ok(tokio::spawn(async move {
+        let result = AssertUnwindSafe(relayer.run(client_config))
+            .catch_unwind()
+            .await;
+        let outcome = match result {
+            Ok(Ok(_)) => "ok",
+            Ok(Err(err)) => {
+                error!(chain = %label, "Relayer exited: {err:?}");
+                "error"
+            }
+            Err(_) => {
+                error!(chain = %label, "Relayer panicked");
+                "panic"
+            }
+        };
+        metrics::RELAYER_EXITS
+            .with_label_values(&[&label, outcome])
+            .inc();
+    }))
+}
+
 /// Upload a single specified image, or, if guest_binary is None, upload all
 /// images in the GUEST_LIST. Returns a list of uploaded image IDs.
 async fn upload_images(
@@ -242,12 +551,19 @@ async fn upload_images(
         .await?;
         let img_id = image_id.clone();
 
-        upload_img(
+        let uploaded = upload_img(
             bonsai_client.clone(),
             img_id.clone(),
             guest_entry.elf.to_vec(),
         )
-        .await?;
+        .await;
+        let result = if uploaded.is_ok() {
+            "success"
+        } else {
+            "failure"
+        };
+        metrics::IMAGE_UPLOADS.with_label_values(&[result]).inc();
+        uploaded?;
 
         image_ids.push(guest_entry.image_id.into());
     }
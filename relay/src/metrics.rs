@@ -0,0 +1,249 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prometheus metrics exposed by the relay CLI on `/metrics`.
+
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use ethers::{
+    providers::{Middleware, Provider, Ws},
+    types::{Address, U64},
+};
+use futures::StreamExt;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
+};
+use tracing::warn;
+
+/// Delay before the first attempt to follow blocks again after an error,
+/// doubled after each consecutive failure.
+const TRACKING_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between two attempts to follow blocks.
+const TRACKING_MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Bucket boundaries, in seconds, for Bonsai proving phases.
+const SESSION_DURATION_BUCKETS: &[f64] = &[
+    5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 2400.0, 3600.0,
+];
+
+/// Proof requests sent to Bonsai, labelled by guest name.
+pub static PROOF_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(register_int_counter_vec!(
+        "bonsai_proof_requests_total",
+        "Proof requests sent to Bonsai",
+        &["guest"]
+    ))
+});
+
+/// Duration of successful Bonsai sessions, labelled by `stark` or `snark`
+/// phase.
+pub static SESSION_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(register_histogram_vec!(
+        "bonsai_session_duration_seconds",
+        "Duration of successful Bonsai proving sessions",
+        &["phase"],
+        SESSION_DURATION_BUCKETS.to_vec()
+    ))
+});
+
+/// Failed Bonsai sessions, labelled by phase and the status reported by
+/// Bonsai.
+pub static SESSION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(register_int_counter_vec!(
+        "bonsai_session_failures_total",
+        "Bonsai proving sessions that exited with a bad status",
+        &["phase", "status"]
+    ))
+});
+
+/// Guest image uploads, labelled by `success` or `failure`.
+pub static IMAGE_UPLOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(register_int_counter_vec!(
+        "bonsai_image_uploads_total",
+        "Guest images uploaded to Bonsai",
+        &["result"]
+    ))
+});
+
+/// Callback transactions sent by the relayer, labelled by chain and receipt
+/// outcome.
+pub static CALLBACK_TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(register_int_counter_vec!(
+        "bonsai_callback_transactions_total",
+        "Callback transactions sent by the relayer to the relay contract",
+        &["chain", "outcome"]
+    ))
+});
+
+/// Errors while following callback transactions, labelled by chain. Each one
+/// is followed by a reconnection to the node.
+pub static CALLBACK_TRACKING_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(register_int_counter_vec!(
+        "bonsai_callback_tracking_errors_total",
+        "Errors while following callback transactions on-chain",
+        &["chain"]
+    ))
+});
+
+/// Exits of the relayer tasks, labelled by chain and `ok`, `error` or
+/// `panic`.
+pub static RELAYER_EXITS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(register_int_counter_vec!(
+        "bonsai_relayer_exits_total",
+        "Exits of the relayer task",
+        &["chain", "outcome"]
+    ))
+});
+
+/// Keeper evaluations, labelled by the decision taken.
+pub static KEEPER_DECISIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(register_int_counter_vec!(
+        "bonsai_keeper_decisions_total",
+        "Keeper evaluations of the vault",
+        &["decision"]
+    ))
+});
+
+/// Metric names are static, so registration only fails on a programming error.
+#[allow(clippy::expect_used)]
+fn register<T>(metric: prometheus::Result<T>) -> T {
+    metric.expect("metric should register in the default registry")
+}
+
+/// Serve the default Prometheus registry on `http://{addr}/metrics`.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+    Server::try_bind(&addr)
+        .with_context(|| format!("Failed to bind metrics server to {addr}"))?
+        .serve(make_service)
+        .await
+        .context("Metrics server failed")
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let mut buffer = Vec::new();
+    if let Err(err) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        let mut response = Response::new(Body::from(err.to_string()));
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        return Ok(response);
+    }
+
+    let mut response = Response::new(Body::from(buffer));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(prometheus::TEXT_FORMAT),
+    );
+    Ok(response)
+}
+
+/// Follow new blocks and record the receipt status of every transaction
+/// `sender` sends to the relay contract.
+///
+/// The relayer does not expose its callback submissions, so they are observed
+/// on-chain instead. Connection, subscription and RPC errors are logged and
+/// counted in [CALLBACK_TRACKING_ERRORS], then the node is reconnected to
+/// after an exponential backoff, so this never returns.
+pub async fn track_callback_transactions(
+    eth_node: &str,
+    chain: &str,
+    relay_address: Address,
+    sender: Address,
+) {
+    let mut delay = TRACKING_RETRY_DELAY;
+    loop {
+        let err =
+            follow_callback_transactions(eth_node, chain, relay_address, sender, &mut delay).await;
+        CALLBACK_TRACKING_ERRORS.with_label_values(&[chain]).inc();
+        warn!(
+            chain,
+            "Failed to follow callback transactions, retrying in {delay:?}: {err:?}"
+        );
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(TRACKING_MAX_RETRY_DELAY);
+    }
+}
+
+/// Follow blocks until the first error, resetting `delay` after every block
+/// handled.
+async fn follow_callback_transactions(
+    eth_node: &str,
+    chain: &str,
+    relay_address: Address,
+    sender: Address,
+    delay: &mut Duration,
+) -> anyhow::Error {
+    let result: Result<()> = async {
+        let provider = Provider::<Ws>::connect(eth_node)
+            .await
+            .context("Failed to connect to Ethereum node")?;
+        let mut blocks = provider
+            .subscribe_blocks()
+            .await
+            .context("Failed to subscribe to new blocks")?;
+        while let Some(block) = blocks.next().await {
+            let Some(number) = block.number else {
+                continue;
+            };
+            let Some(block) = provider
+                .get_block_with_txs(number)
+                .await
+                .context("Failed to fetch block transactions")?
+            else {
+                continue;
+            };
+
+            for tx in block
+                .transactions
+                .iter()
+                .filter(|tx| tx.from == sender && tx.to == Some(relay_address))
+            {
+                let receipt = provider
+                    .get_transaction_receipt(tx.hash)
+                    .await
+                    .context("Failed to fetch callback receipt")?;
+                let outcome = match receipt.and_then(|receipt| receipt.status) {
+                    Some(status) if status == U64::one() => "success",
+                    Some(_) => "reverted",
+                    None => "unknown",
+                };
+                CALLBACK_TRANSACTIONS
+                    .with_label_values(&[chain, outcome])
+                    .inc();
+            }
+            *delay = TRACKING_RETRY_DELAY;
+        }
+        Ok(())
+    }
+    .await;
+    match result {
+        Ok(()) => anyhow!("Block subscription closed"),
+        Err(err) => err,
+    }
+}
@@ -0,0 +1,612 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Native what-if simulation of the optimal allocation.
+//!
+//! Runs the same `allocation` code as the `optimal_allocation` guest, without
+//! the zkVM, on a vault snapshot read from JSON. Amounts are decimal strings,
+//! or integers when they fit in a `u64`. Optional fields default to zero.
+//!
+//! ```json
+//! {
+//!   "chunk_count": 100,
+//!   "total_available_amount": "14000000000000000000000",
+//!   "objective": "total_interest",
+//!   "strategies": [
+//!     {
+//!       "address": "0x0000000000000000000000000000000000000001",
+//!       "current_debt": "0",
+//!       "max_debt": "6000000000000000000000",
+//!       "pair": {
+//!         "cur_timestamp": 1697739119,
+//!         "last_timestamp": 1694820803,
+//!         "rate_per_sec": 162996627,
+//!         "full_utilization_rate": 1582470460,
+//!         "total_asset": "1000515508599392284665549",
+//!         "total_borrow": "14607601723157079315388",
+//!         "util_prec": 100000,
+//!         "min_target_util": 75000,
+//!         "max_target_util": 85000,
+//!         "vertex_utilization": 87500,
+//!         "min_full_util_rate": 1582470460,
+//!         "max_full_util_rate": 3164940920000,
+//!         "zero_util_rate": 158247046,
+//!         "rate_half_life": 172800,
+//!         "vertex_rate_percent": "200000000000000000",
+//!         "rate_prec": "1000000000000000000",
+//!         "fee_to_protocol_rate": 10000
+//!       },
+//!       "rate_model": { "kind": "linear_kink", "base_rate": 0, "slope1": 1000000000, "slope2": 30000000000, "kink": 80000 }
+//!     }
+//!   ],
+//!   "constraints": { "max_share_bps": 4000, "min_idle": "0", "limit_withdrawals": true },
+//!   "cost_model": { "gas_cost": "10000000000000000", "asset_price": "500000000000000", "holding_horizon": 2592000 },
+//!   "rate_horizon": 86400
+//! }
+//! ```
+
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use allocation::{
+    optimal_allocation, AllocationError, AllocationInput, AllocationResult, Constraints, CostModel,
+    Objective, Position, RateModelKind, RateModelParams, StrategyParams, StrategyReport,
+    SturdyDataParams, APR_PRECISION,
+};
+use alloy_primitives::{Address, U256};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+
+/// Vault state and allocation settings to simulate.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VaultSnapshot {
+    /// Maximum number of solver bisection steps.
+    pub chunk_count: u64,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub total_initial_amount: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub total_available_amount: U256,
+    #[serde(default)]
+    pub objective: ObjectiveSnapshot,
+    pub strategies: Vec<StrategySnapshot>,
+    #[serde(default)]
+    pub constraints: ConstraintsSnapshot,
+    #[serde(default)]
+    pub cost_model: CostModelSnapshot,
+    /// Seconds over which Sturdy rates are averaged, instantaneous when
+    /// unset.
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub rate_horizon: U256,
+}
+
+/// Quantity maximized by the allocation.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectiveSnapshot {
+    #[default]
+    TotalInterest,
+    MarginalRate,
+}
+
+/// A strategy of the vault, with its pair.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrategySnapshot {
+    pub address: Address,
+    /// Debt the allocation starts from, on top of which the available
+    /// amount is distributed.
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub initial_debt: U256,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub activation: U256,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub last_report: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub current_debt: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub max_debt: U256,
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub min_debt: U256,
+    pub pair: PairSnapshot,
+    /// Sturdy when unset.
+    #[serde(default)]
+    pub rate_model: RateModelSnapshot,
+}
+
+/// Rate state and curve constants of a Sturdy pair.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PairSnapshot {
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub cur_timestamp: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub last_timestamp: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub rate_per_sec: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub full_utilization_rate: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub total_asset: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub total_borrow: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub util_prec: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub min_target_util: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub max_target_util: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub vertex_utilization: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub min_full_util_rate: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub max_full_util_rate: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub zero_util_rate: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub rate_half_life: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub vertex_rate_percent: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub rate_prec: U256,
+    #[serde(default)]
+    pub is_interest_paused: bool,
+    /// Protocol fee in 1e5 precision, none when unset.
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub fee_to_protocol_rate: U256,
+}
+
+/// Rate model of a strategy's pair.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateModelSnapshot {
+    pub kind: RateModelKindSnapshot,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub base_rate: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub slope1: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub slope2: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub kink: U256,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateModelKindSnapshot {
+    #[default]
+    Sturdy,
+    LinearKink,
+    Fixed,
+}
+
+/// Vault policy limits, see [Constraints]. Minimum debts are set per
+/// strategy.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConstraintsSnapshot {
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub max_share_bps: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub min_idle: U256,
+    pub limit_withdrawals: bool,
+}
+
+/// Rebalance cost model, see [CostModel].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CostModelSnapshot {
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub gas_cost: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub asset_price: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub holding_horizon: U256,
+    #[serde(deserialize_with = "deserialize_amount")]
+    pub min_net_gain: U256,
+}
+
+impl VaultSnapshot {
+    /// Read a snapshot from a JSON file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read vault snapshot {}", path.display()))?;
+        let snapshot: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse vault snapshot {}", path.display()))?;
+        if snapshot.strategies.is_empty() {
+            bail!("Vault snapshot must list at least one strategy");
+        }
+        Ok(snapshot)
+    }
+
+    /// Guest input matching the snapshot.
+    pub fn to_input(&self) -> AllocationInput {
+        let strategies = &self.strategies;
+        AllocationInput {
+            chunk_count: self.chunk_count,
+            total_initial_amount: self.total_initial_amount,
+            total_available_amount: self.total_available_amount,
+            initial_datas: strategies
+                .iter()
+                .map(|strategy| Position {
+                    strategy: strategy.address,
+                    debt: strategy.initial_debt,
+                })
+                .collect(),
+            strategy_datas: strategies
+                .iter()
+                .map(|strategy| StrategyParams {
+                    activation: strategy.activation,
+                    last_report: strategy.last_report,
+                    current_debt: strategy.current_debt,
+                    max_debt: strategy.max_debt,
+                })
+                .collect(),
+            sturdy_datas: strategies
+                .iter()
+                .map(|strategy| strategy.pair.to_params())
+                .collect(),
+            objective: match self.objective {
+                ObjectiveSnapshot::TotalInterest => Objective::TotalInterest,
+                ObjectiveSnapshot::MarginalRate => Objective::MarginalRate,
+            },
+            constraints: Constraints {
+                min_debts: strategies
+                    .iter()
+                    .map(|strategy| strategy.min_debt)
+                    .collect(),
+                max_share_bps: self.constraints.max_share_bps,
+                min_idle: self.constraints.min_idle,
+                limit_withdrawals: self.constraints.limit_withdrawals,
+            },
+            cost_model: CostModel {
+                gas_cost: self.cost_model.gas_cost,
+                asset_price: self.cost_model.asset_price,
+                holding_horizon: self.cost_model.holding_horizon,
+                min_net_gain: self.cost_model.min_net_gain,
+            },
+            vault: Address::ZERO,
+            snapshot_timestamp: strategies
+                .iter()
+                .map(|strategy| strategy.pair.cur_timestamp)
+                .max()
+                .unwrap_or_default(),
+            rate_models: strategies
+                .iter()
+                .map(|strategy| strategy.rate_model.to_params())
+                .collect(),
+            rate_horizon: self.rate_horizon,
+        }
+    }
+}
+
+impl PairSnapshot {
+    fn to_params(&self) -> SturdyDataParams {
+        SturdyDataParams {
+            cur_timestamp: self.cur_timestamp,
+            last_timestamp: self.last_timestamp,
+            rate_per_sec: self.rate_per_sec,
+            full_utilization_rate: self.full_utilization_rate,
+            total_asset: self.total_asset,
+            total_borrow: self.total_borrow,
+            util_prec: self.util_prec,
+            min_target_util: self.min_target_util,
+            max_target_util: self.max_target_util,
+            vertex_utilization: self.vertex_utilization,
+            min_full_util_rate: self.min_full_util_rate,
+            max_full_util_rate: self.max_full_util_rate,
+            zero_util_rate: self.zero_util_rate,
+            rate_half_life: self.rate_half_life,
+            vertex_rate_percent: self.vertex_rate_percent,
+            rate_prec: self.rate_prec,
+            is_interest_paused: self.is_interest_paused,
+            fee_to_protocol_rate: self.fee_to_protocol_rate,
+        }
+    }
+}
+
+impl RateModelSnapshot {
+    fn to_params(&self) -> RateModelParams {
+        RateModelParams {
+            kind: match self.kind {
+                RateModelKindSnapshot::Sturdy => RateModelKind::Sturdy,
+                RateModelKindSnapshot::LinearKink => RateModelKind::LinearKink,
+                RateModelKindSnapshot::Fixed => RateModelKind::Fixed,
+            },
+            base_rate: self.base_rate,
+            slope1: self.slope1,
+            slope2: self.slope2,
+            kink: self.kink,
+        }
+    }
+}
+
+/// Outcome of one simulated scenario.
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    pub chunk_count: u64,
+    pub total_available_amount: U256,
+    /// Result the guest would commit. Its reports project the proposed debts
+    /// of every strategy, whether or not the proposal beats the current
+    /// allocation.
+    pub result: AllocationResult,
+    pub elapsed: Duration,
+}
+
+/// Run the allocation natively on `input`.
+pub fn simulate_allocation(input: &AllocationInput) -> Simulation {
+    let start = Instant::now();
+    let result = optimal_allocation(input).unwrap_or_else(AllocationResult::failure);
+    Simulation {
+        chunk_count: input.chunk_count,
+        total_available_amount: input.total_available_amount,
+        result,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Simulate every combination of `chunk_counts` and `total_available_amounts`.
+/// An empty list keeps the value of `input`.
+pub fn sweep(
+    input: &AllocationInput,
+    chunk_counts: &[u64],
+    total_available_amounts: &[U256],
+) -> Vec<Simulation> {
+    let chunk_counts = if chunk_counts.is_empty() {
+        vec![input.chunk_count]
+    } else {
+        chunk_counts.to_vec()
+    };
+    let total_available_amounts = if total_available_amounts.is_empty() {
+        vec![input.total_available_amount]
+    } else {
+        total_available_amounts.to_vec()
+    };
+
+    let mut simulations = Vec::with_capacity(chunk_counts.len() * total_available_amounts.len());
+    for chunk_count in &chunk_counts {
+        for total_available_amount in &total_available_amounts {
+            let mut scenario = input.clone();
+            scenario.chunk_count = *chunk_count;
+            scenario.total_available_amount = *total_available_amount;
+            simulations.push(simulate_allocation(&scenario));
+        }
+    }
+    simulations
+}
+
+/// Parse a decimal amount given on the command line.
+pub fn parse_amount(value: &str) -> Result<U256> {
+    U256::from_str_radix(value, 10).with_context(|| format!("invalid decimal integer: {value}"))
+}
+
+/// Accept amounts either as integers or as decimal strings, since they can
+/// exceed the JSON integer range.
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<U256, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        Integer(u64),
+        Decimal(String),
+    }
+
+    match Amount::deserialize(deserializer)? {
+        Amount::Integer(value) => Ok(U256::from(value)),
+        Amount::Decimal(value) => parse_amount(&value).map_err(serde::de::Error::custom),
+    }
+}
+
+/// `value / precision` as a percentage with four decimals, or `-` when the
+/// precision is zero or the value too large to scale.
+pub(crate) fn percent(value: U256, precision: U256) -> String {
+    let basis = match value.checked_mul(U256::from(1_000_000)) {
+        Some(scaled) if precision != U256::ZERO => scaled / precision,
+        _ => return "-".to_string(),
+    };
+    let whole = basis / U256::from(10_000);
+    let fraction = basis % U256::from(10_000);
+    format!("{whole}.{fraction:0>4}%")
+}
+
+/// Error code committed by the guest, with its reason when it is known.
+pub(crate) fn error_code(code: u8) -> String {
+    match AllocationError::from_code(code) {
+        Some(error) => format!("{code} ({error})"),
+        None => code.to_string(),
+    }
+}
+
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = &self.result;
+        let apr_precision = U256::from(APR_PRECISION);
+        writeln!(
+            f,
+            "chunk_count {} total_available {} in {}us",
+            self.chunk_count,
+            self.total_available_amount,
+            self.elapsed.as_micros()
+        )?;
+        if result.error_code != 0 {
+            return writeln!(
+                f,
+                "  failed with error code {}",
+                error_code(result.error_code)
+            );
+        }
+
+        for report in &result.reports {
+            write_report(f, report)?;
+        }
+        writeln!(
+            f,
+            "  vault APR {} -> {} interest {} -> {} net gain {} unallocated {} success {}",
+            percent(U256::from(result.current_apr), apr_precision),
+            percent(U256::from(result.new_apr), apr_precision),
+            result.current_interest,
+            result.new_interest,
+            result.net_gain,
+            result.unallocated_amount,
+            result.is_success
+        )
+    }
+}
+
+/// One line describing the move of a strategy from its current to its target
+/// debt.
+pub(crate) fn write_report(f: &mut fmt::Formatter<'_>, report: &StrategyReport) -> fmt::Result {
+    let apr_precision = U256::from(APR_PRECISION);
+    let (sign, change) = if report.target_debt >= report.current_debt {
+        ("+", report.target_debt - report.current_debt)
+    } else {
+        ("-", report.current_debt - report.target_debt)
+    };
+    writeln!(
+        f,
+        "  {} debt {} -> {} ({sign}{change}) utilization {} -> {} rate/s {} -> {} APR {} -> {}",
+        report.strategy,
+        report.current_debt,
+        report.target_debt,
+        percent(report.current_utilization, report.util_prec),
+        percent(report.projected_utilization, report.util_prec),
+        report.current_rate_per_sec,
+        report.projected_rate_per_sec,
+        percent(report.current_apr, apr_precision),
+        percent(report.projected_apr, apr_precision),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// JSON example of the module documentation.
+    fn doc_example() -> String {
+        include_str!("simulator.rs")
+            .lines()
+            .skip_while(|line| *line != "//! ```json")
+            .skip(1)
+            .take_while(|line| *line != "//! ```")
+            .map(|line| line.trim_start_matches("//!"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[derive(Deserialize)]
+    struct Amount {
+        #[serde(deserialize_with = "deserialize_amount")]
+        value: U256,
+    }
+
+    fn amount(json: &str) -> serde_json::Result<U256> {
+        serde_json::from_str::<Amount>(json).map(|amount| amount.value)
+    }
+
+    #[test]
+    fn parses_the_doc_example() -> Result<()> {
+        let snapshot: VaultSnapshot = serde_json::from_str(&doc_example())?;
+        assert_eq!(snapshot.chunk_count, 100);
+        assert_eq!(
+            snapshot.total_available_amount,
+            parse_amount("14000000000000000000000")?
+        );
+        assert_eq!(snapshot.rate_horizon, U256::from(86400));
+        assert_eq!(snapshot.strategies.len(), 1);
+        let strategy = &snapshot.strategies[0];
+        assert_eq!(strategy.address, Address::with_last_byte(1));
+        assert_eq!(strategy.initial_debt, U256::ZERO);
+        assert_eq!(
+            strategy.pair.rate_prec,
+            parse_amount("1000000000000000000")?
+        );
+        assert_eq!(strategy.pair.fee_to_protocol_rate, U256::from(10_000));
+        assert!(matches!(
+            strategy.rate_model.kind,
+            RateModelKindSnapshot::LinearKink
+        ));
+        assert_eq!(snapshot.constraints.max_share_bps, U256::from(4000));
+        assert!(snapshot.constraints.limit_withdrawals);
+        assert_eq!(snapshot.cost_model.min_net_gain, U256::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_amounts_as_integers_or_decimal_strings() -> Result<()> {
+        assert_eq!(amount(r#"{"value": 42}"#)?, U256::from(42));
+        assert_eq!(amount(r#"{"value": "42"}"#)?, U256::from(42));
+        assert_eq!(
+            amount(r#"{"value": "100000000000000000000000"}"#)?,
+            U256::from(10u64).pow(U256::from(23))
+        );
+        assert!(amount(r#"{"value": "4.2"}"#).is_err());
+        assert!(amount(r#"{"value": "0x2a"}"#).is_err());
+        assert!(amount(r#"{"value": -1}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn builds_the_guest_input() -> Result<()> {
+        let snapshot: VaultSnapshot = serde_json::from_str(&doc_example())?;
+        let input = snapshot.to_input();
+        let strategy = &snapshot.strategies[0];
+        assert_eq!(input.chunk_count, 100);
+        assert_eq!(input.objective, Objective::TotalInterest);
+        assert_eq!(input.initial_datas[0].strategy, strategy.address);
+        assert_eq!(input.strategy_datas[0].max_debt, strategy.max_debt);
+        assert_eq!(input.sturdy_datas[0].total_asset, strategy.pair.total_asset);
+        assert_eq!(input.constraints.min_debts, vec![U256::ZERO]);
+        assert_eq!(input.cost_model.holding_horizon, U256::from(2_592_000));
+        assert_eq!(input.rate_models[0].kink, U256::from(80_000));
+        assert_eq!(input.vault, Address::ZERO);
+        assert_eq!(input.snapshot_timestamp, U256::from(1_697_739_119));
+        Ok(())
+    }
+
+    #[test]
+    fn sweeps_every_combination() -> Result<()> {
+        let input = serde_json::from_str::<VaultSnapshot>(&doc_example())?.to_input();
+        let amounts = [U256::from(1), U256::from(2), U256::from(3)];
+        let simulations = sweep(&input, &[10, 100], &amounts);
+        assert_eq!(simulations.len(), 6);
+        assert_eq!(simulations[2].chunk_count, 10);
+        assert_eq!(simulations[2].total_available_amount, amounts[2]);
+        assert_eq!(simulations[3].chunk_count, 100);
+        assert_eq!(simulations[3].total_available_amount, amounts[0]);
+
+        let simulations = sweep(&input, &[], &[]);
+        assert_eq!(simulations.len(), 1);
+        assert_eq!(simulations[0].chunk_count, input.chunk_count);
+        assert_eq!(
+            simulations[0].total_available_amount,
+            input.total_available_amount
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn formats_percentages() {
+        let precision = U256::from(1_000_000);
+        assert_eq!(percent(U256::from(123_456), precision), "12.3456%");
+        assert_eq!(percent(U256::from(5), precision), "0.0005%");
+        assert_eq!(percent(U256::from(1), U256::ZERO), "-");
+        assert_eq!(percent(U256::MAX, precision), "-");
+    }
+}
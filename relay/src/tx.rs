@@ -0,0 +1,307 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transaction policy: retries, confirmation timeout, EIP-1559 fee caps and
+//! replacement of stuck transactions.
+//!
+//! Transactions sent by this CLI, such as the keeper requests, go through
+//! [send_with_policy] and honour every setting. Callback transactions are
+//! out of scope for now: `bonsai-ethereum-relay` 0.5.1 signs and sends them
+//! from a client it builds internally out of its `EthersClientConfig`, with
+//! no hook for a fee or replacement middleware, and only takes the retry
+//! count and confirmation timeout. [TxPolicy::relayer_settings] therefore
+//! rejects a policy setting anything else rather than ignoring it, until the
+//! relayer accepts a caller-supplied client.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, TransactionReceipt, TxHash, U256, U64},
+};
+use serde::{Deserialize, Deserializer};
+use tracing::warn;
+
+use crate::parse_u256;
+
+/// Interval between two receipt lookups while waiting for confirmation.
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Percentage by which fees are raised when the policy does not set one.
+const DEFAULT_FEE_BUMP_PERCENT: u64 = 15;
+
+/// Replacements of a stuck transaction when the policy does not set a limit.
+const DEFAULT_MAX_REPLACEMENTS: u32 = 3;
+
+/// How transactions are priced, retried and replaced.
+#[derive(Args, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TxPolicy {
+    /// Number of times a failed transaction submission is retried.
+    #[arg(long, env, default_value_t = 1)]
+    pub tx_retries: u64,
+
+    /// Seconds to wait for a transaction to be mined before it is considered
+    /// stuck.
+    #[arg(long, env, default_value_t = 10)]
+    pub tx_confirmation_timeout_secs: u64,
+
+    /// Cap, in wei, on the EIP-1559 max fee per gas.
+    #[arg(long, env, value_parser = parse_u256)]
+    #[serde(deserialize_with = "deserialize_wei")]
+    pub max_fee_per_gas: Option<U256>,
+
+    /// Cap, in wei, on the EIP-1559 priority fee per gas.
+    #[arg(long, env, value_parser = parse_u256)]
+    #[serde(deserialize_with = "deserialize_wei")]
+    pub max_priority_fee_per_gas: Option<U256>,
+
+    /// Refuse to send transactions while the estimated max fee per gas, in
+    /// wei, is above this ceiling.
+    #[arg(long, env, value_parser = parse_u256)]
+    #[serde(deserialize_with = "deserialize_wei")]
+    pub gas_price_ceiling: Option<U256>,
+
+    /// Percentage by which fees are raised when replacing a stuck
+    /// transaction. Defaults to 15.
+    #[arg(long, env)]
+    pub fee_bump_percent: Option<u64>,
+
+    /// Number of times a stuck transaction is replaced before giving up.
+    /// Defaults to 3.
+    #[arg(long, env)]
+    pub max_replacements: Option<u32>,
+}
+
+impl Default for TxPolicy {
+    fn default() -> Self {
+        Self {
+            tx_retries: 1,
+            tx_confirmation_timeout_secs: 10,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            gas_price_ceiling: None,
+            fee_bump_percent: None,
+            max_replacements: None,
+        }
+    }
+}
+
+impl TxPolicy {
+    pub fn confirmation_timeout(&self) -> Duration {
+        Duration::from_secs(self.tx_confirmation_timeout_secs)
+    }
+
+    pub fn fee_bump_percent(&self) -> u64 {
+        self.fee_bump_percent.unwrap_or(DEFAULT_FEE_BUMP_PERCENT)
+    }
+
+    pub fn max_replacements(&self) -> u32 {
+        self.max_replacements.unwrap_or(DEFAULT_MAX_REPLACEMENTS)
+    }
+
+    /// Retry count and confirmation timeout, the only settings the relayer
+    /// applies to its callback transactions.
+    ///
+    /// Fails when the policy sets fee caps, a fee ceiling or a replacement
+    /// policy, since the relayer would silently send callbacks without them.
+    pub fn relayer_settings(&self) -> Result<(u64, Duration)> {
+        let unsupported: Vec<&str> = [
+            ("max_fee_per_gas", self.max_fee_per_gas.is_some()),
+            (
+                "max_priority_fee_per_gas",
+                self.max_priority_fee_per_gas.is_some(),
+            ),
+            ("gas_price_ceiling", self.gas_price_ceiling.is_some()),
+            ("fee_bump_percent", self.fee_bump_percent.is_some()),
+            ("max_replacements", self.max_replacements.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, is_set)| is_set.then_some(name))
+        .collect();
+        if !unsupported.is_empty() {
+            bail!(
+                "The relayer cannot apply {} to its callback transactions, which it sends with its \
+                 own client; only tx_retries and tx_confirmation_timeout_secs are supported",
+                unsupported.join(", ")
+            );
+        }
+        Ok((self.tx_retries, self.confirmation_timeout()))
+    }
+
+    /// Max fee per gas, in wei, a transaction is first sent with when the
+    /// estimate is `estimated_max_fee`: the estimate within `max_fee_per_gas`
+    /// and `gas_price_ceiling`.
+    pub fn max_fee(&self, estimated_max_fee: U256) -> U256 {
+        [self.max_fee_per_gas, self.gas_price_ceiling]
+            .into_iter()
+            .flatten()
+            .fold(estimated_max_fee, U256::min)
+    }
+
+    /// Raise `fee` by `fee_bump_percent`, without exceeding `cap`.
+    fn bump(&self, fee: U256, cap: Option<U256>) -> U256 {
+        let bumped = fee + fee * U256::from(self.fee_bump_percent()) / U256::from(100);
+        cap.map_or(bumped, |cap| bumped.min(cap))
+    }
+}
+
+/// Accept fees in TOML either as integers or as decimal strings, since wei
+/// amounts can exceed the TOML integer range.
+fn deserialize_wei<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Wei {
+        Integer(u64),
+        Decimal(String),
+    }
+
+    Option::<Wei>::deserialize(deserializer)?
+        .map(|wei| match wei {
+            Wei::Integer(value) => Ok(U256::from(value)),
+            Wei::Decimal(value) => U256::from_dec_str(&value).map_err(serde::de::Error::custom),
+        })
+        .transpose()
+}
+
+/// Sign and send `tx` as an EIP-1559 transaction priced within `policy`, and
+/// wait for it to be mined.
+///
+/// A transaction that is not mined within the confirmation timeout is
+/// replaced, with the same nonce, at bumped fees. Receipts of every replaced
+/// transaction are still checked since any of them may be the one mined.
+pub async fn send_with_policy<M: Middleware + 'static>(
+    client: &M,
+    tx: TypedTransaction,
+    policy: &TxPolicy,
+) -> Result<TransactionReceipt> {
+    let TypedTransaction::Eip1559(mut tx) = tx else {
+        bail!("Only EIP-1559 transactions can be sent with a transaction policy");
+    };
+
+    let (estimated_max_fee, estimated_priority_fee) = client
+        .estimate_eip1559_fees(None)
+        .await
+        .context("Failed to estimate EIP-1559 fees")?;
+    if let Some(ceiling) = policy.gas_price_ceiling {
+        if estimated_max_fee > ceiling {
+            bail!(
+                "Estimated max fee {estimated_max_fee} wei is above the ceiling of {ceiling} wei"
+            );
+        }
+    }
+    let mut max_fee = policy.max_fee(estimated_max_fee);
+    let mut priority_fee = policy
+        .max_priority_fee_per_gas
+        .map_or(estimated_priority_fee, |cap| {
+            estimated_priority_fee.min(cap)
+        })
+        .min(max_fee);
+
+    // Pin the nonce so that replacements target the same slot.
+    let mut typed = TypedTransaction::Eip1559(tx.clone());
+    client
+        .fill_transaction(&mut typed, None)
+        .await
+        .context("Failed to fill transaction")?;
+    if let TypedTransaction::Eip1559(filled) = typed {
+        tx = filled;
+    }
+
+    let max_replacements = policy.max_replacements();
+    let mut sent = Vec::new();
+    for replacement in 0..=max_replacements {
+        tx.max_fee_per_gas = Some(max_fee);
+        tx.max_priority_fee_per_gas = Some(priority_fee);
+        let typed = TypedTransaction::Eip1559(tx.clone());
+
+        match send_with_retries(client, typed, policy.tx_retries).await {
+            Ok(tx_hash) => sent.push(tx_hash),
+            // A previous submission may have been mined in the meantime,
+            // making the nonce too low.
+            Err(err) if !sent.is_empty() => warn!("Failed to replace transaction: {err:?}"),
+            Err(err) => return Err(err),
+        }
+
+        if let Some(receipt) = wait_for_any(client, &sent, policy.confirmation_timeout()).await? {
+            if receipt.status != Some(U64::one()) {
+                bail!("Transaction {:?} reverted", receipt.transaction_hash);
+            }
+            return Ok(receipt);
+        }
+
+        if replacement < max_replacements {
+            warn!(
+                max_fee = %max_fee,
+                priority_fee = %priority_fee,
+                "Transaction not mined within {}s, replacing it",
+                policy.tx_confirmation_timeout_secs
+            );
+            max_fee = policy.bump(max_fee, policy.max_fee_per_gas);
+            priority_fee = policy
+                .bump(priority_fee, policy.max_priority_fee_per_gas)
+                .min(max_fee);
+        }
+    }
+
+    bail!("Transaction was not mined after {max_replacements} replacements: {sent:?}")
+}
+
+async fn send_with_retries<M: Middleware + 'static>(
+    client: &M,
+    tx: TypedTransaction,
+    retries: u64,
+) -> Result<TxHash> {
+    let mut attempt = 0;
+    loop {
+        match client.send_transaction(tx.clone(), None).await {
+            Ok(pending) => return Ok(pending.tx_hash()),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                warn!("Failed to send transaction, retrying ({attempt}/{retries}): {err}");
+                tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+            }
+            Err(err) => return Err(err).context("Failed to send transaction"),
+        }
+    }
+}
+
+/// Wait up to `timeout` for any of `tx_hashes` to be mined.
+async fn wait_for_any<M: Middleware + 'static>(
+    client: &M,
+    tx_hashes: &[TxHash],
+    timeout: Duration,
+) -> Result<Option<TransactionReceipt>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        for tx_hash in tx_hashes {
+            let receipt = client
+                .get_transaction_receipt(*tx_hash)
+                .await
+                .context("Failed to fetch transaction receipt")?;
+            if receipt.is_some() {
+                return Ok(receipt);
+            }
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+pragma solidity 0.8.21;
+
+import {Test} from "forge-std/Test.sol";
+import {ZKOptimalAllocation} from "contracts/ZKOptimalAllocation.sol";
+import {VariableInterestRate} from "./utils/VariableInterestRate.sol";
+
+// Differential test of the guest's Sturdy rate model against the Solidity math.
+contract VariableInterestRateTest is Test {
+    uint256[4] deltaTimes = [uint256(0), 1 hours, 1 days, 30 days];
+    uint256[8] utilizations = [uint256(0), 30000, 74999, 75000, 80000, 87500, 95000, 100000];
+
+    // Rate curve of the Sturdy pairs used by ZKOptimalAllocationTest.
+    function testMatchesPairRateModel() public {
+        VariableInterestRate rate = new VariableInterestRate(
+            87500,
+            200000000000000000,
+            75000,
+            85000,
+            158247046,
+            1582470460,
+            3164940920000,
+            172800
+        );
+        _assertMatches(rate);
+    }
+
+    // Steeper curve with a wider target range, exercising both decay branches more.
+    function testMatchesSteepRateModel() public {
+        VariableInterestRate rate = new VariableInterestRate(
+            80000,
+            500000000000000000,
+            50000,
+            90000,
+            0,
+            1000000000,
+            100000000000000,
+            43200
+        );
+        _assertMatches(rate);
+    }
+
+    function _assertMatches(VariableInterestRate rate) internal {
+        uint64[3] memory fullUtilizationRates = [
+            rate.MIN_FULL_UTIL_RATE(),
+            (rate.MIN_FULL_UTIL_RATE() + rate.MAX_FULL_UTIL_RATE()) / 2,
+            rate.MAX_FULL_UTIL_RATE()
+        ];
+        uint256 caseCount = deltaTimes.length * utilizations.length * fullUtilizationRates.length;
+        ZKOptimalAllocation.SturdyStrategyDataParams[] memory datas =
+            new ZKOptimalAllocation.SturdyStrategyDataParams[](caseCount);
+        uint256[] memory caseUtilizations = new uint256[](caseCount);
+
+        uint256 index;
+        for (uint256 i; i < deltaTimes.length; ++i) {
+            for (uint256 j; j < utilizations.length; ++j) {
+                for (uint256 k; k < fullUtilizationRates.length; ++k) {
+                    datas[index] = _sturdyData(rate, deltaTimes[i], fullUtilizationRates[k]);
+                    caseUtilizations[index] = utilizations[j];
+                    ++index;
+                }
+            }
+        }
+
+        (uint64[] memory ratesPerSec, uint64[] memory newFullUtilizationRates) = _guestRates(datas, caseUtilizations);
+        assertEq(ratesPerSec.length, caseCount);
+        assertEq(newFullUtilizationRates.length, caseCount);
+        for (uint256 c; c < caseCount; ++c) {
+            (uint64 expectedRate, uint64 expectedFullRate) = rate.getNewRate(
+                datas[c].curTimestamp - datas[c].lastTimestamp,
+                caseUtilizations[c],
+                uint64(datas[c].fullUtilizationRate)
+            );
+            assertEq(ratesPerSec[c], expectedRate);
+            assertEq(newFullUtilizationRates[c], expectedFullRate);
+        }
+    }
+
+    function _sturdyData(
+        VariableInterestRate rate,
+        uint256 deltaTime,
+        uint64 fullUtilizationRate
+    ) internal view returns (ZKOptimalAllocation.SturdyStrategyDataParams memory data) {
+        data.curTimestamp = block.timestamp + deltaTime;
+        data.lastTimestamp = block.timestamp;
+        data.fullUtilizationRate = fullUtilizationRate;
+        data.UTIL_PREC = rate.UTIL_PREC();
+        data.MIN_TARGET_UTIL = rate.MIN_TARGET_UTIL();
+        data.MAX_TARGET_UTIL = rate.MAX_TARGET_UTIL();
+        data.VERTEX_UTILIZATION = rate.VERTEX_UTILIZATION();
+        data.MIN_FULL_UTIL_RATE = rate.MIN_FULL_UTIL_RATE();
+        data.MAX_FULL_UTIL_RATE = rate.MAX_FULL_UTIL_RATE();
+        data.ZERO_UTIL_RATE = rate.ZERO_UTIL_RATE();
+        data.RATE_HALF_LIFE = rate.RATE_HALF_LIFE();
+        data.VERTEX_RATE_PERCENT = rate.VERTEX_RATE_PERCENT();
+        data.RATE_PREC = rate.RATE_PREC();
+    }
+
+    // Evaluate the Rust rate model through the `variable_interest_rate` example.
+    function _guestRates(
+        ZKOptimalAllocation.SturdyStrategyDataParams[] memory datas,
+        uint256[] memory caseUtilizations
+    ) internal returns (uint64[] memory, uint64[] memory) {
+        string[] memory command = new string[](8);
+        command[0] = "cargo";
+        command[1] = "run";
+        command[2] = "-q";
+        command[3] = "-p";
+        command[4] = "bonsai-starter-allocation";
+        command[5] = "--example";
+        command[6] = "variable_interest_rate";
+        command[7] = vm.toString(abi.encode(datas, caseUtilizations));
+        return abi.decode(vm.ffi(command), (uint64[], uint64[]));
+    }
+}
@@ -20,6 +20,7 @@ import {BonsaiTest} from "bonsai/BonsaiTest.sol";
 import {IBonsaiRelay} from "bonsai/IBonsaiRelay.sol";
 import {ZKOptimalAllocation} from "contracts/ZKOptimalAllocation.sol";
 import {IDebtManager} from "../contracts/interfaces/IDebtManager.sol";
+import {ILendingMarketStrategy} from "../contracts/interfaces/ILendingMarketStrategy.sol";
 import {IVault} from "../contracts/interfaces/IVault.sol";
 
 contract ZKOptimalAllocationTest is BonsaiTest {
@@ -45,10 +46,226 @@ contract ZKOptimalAllocationTest is BonsaiTest {
         // Anticipate a callback invocation on the starter contract
         vm.expectCall(address(starter), abi.encodeWithSelector(ZKOptimalAllocation.onResult.selector));
         // Relay the solution as a callback using simulated data
-        uint64 BONSAI_CALLBACK_GAS_LIMIT = 1000000;
-        uint256 chunkCount = 100;
-        uint256 totalInitialAmount;
-        uint256 totalAvailable = 14000 * 10 ** 18;
+        uint64 BONSAI_CALLBACK_GAS_LIMIT = 3000000;
+        bytes memory input = _offChainInput();
+        starter.expectResult(keccak256(input));
+        runCallbackRequest(
+            imageId,
+            input,
+            address(starter), 
+            starter.onResult.selector, 
+            BONSAI_CALLBACK_GAS_LIMIT
+        );
+
+        // Validate the optimal allocation value
+        (
+            IDebtManager.StrategyAllocation[] memory allocations, 
+            uint256 newAPR, 
+            uint256 curAPR, 
+            bool isSuccess
+        ) = starter.getResult(address(0));
+
+        // Lenders of the barely used pair of strategy 1 earn the least, so it only gets what the others cannot take.
+        assertEq(allocations[0].strategy, address(4));
+        assertEq(allocations[0].debt, uint256(3000000000000000000000));
+        assertEq(allocations[1].strategy, address(3));
+        assertEq(allocations[1].debt, uint256(3000000000000000000000));
+        assertEq(allocations[2].strategy, address(2));
+        assertEq(allocations[2].debt, uint256(5000000000000000000000));
+        assertEq(allocations[3].strategy, address(1));
+        assertEq(allocations[3].debt, uint256(3000000000000000000000));
+        // Every strategy starts empty, so the actions are deposits, largest first.
+        ZKOptimalAllocation.RebalanceAction[] memory actions = starter.getActions(address(0));
+        assertEq(actions.length, 4);
+        assertEq(actions[0].strategy, address(2));
+        assertEq(actions[0].amount, uint256(5000000000000000000000));
+        assertEq(actions[1].strategy, address(4));
+        assertEq(actions[1].amount, uint256(3000000000000000000000));
+        assertEq(actions[2].strategy, address(3));
+        assertEq(actions[2].amount, uint256(3000000000000000000000));
+        assertEq(actions[3].strategy, address(1));
+        assertEq(actions[3].amount, uint256(3000000000000000000000));
+        for (uint256 i; i < actions.length; ++i) {
+            assertEq(uint8(actions[i].direction), uint8(ZKOptimalAllocation.RebalanceDirection.Deposit));
+        }
+        // Reports follow the input order and project the allocated debts.
+        ZKOptimalAllocation.StrategyReport[] memory reports = starter.getStrategyReports(address(0));
+        assertEq(reports.length, 4);
+        for (uint256 i; i < reports.length; ++i) {
+            assertEq(reports[i].strategy, address(uint160(i + 1)));
+            assertEq(reports[i].currentDebt, 0);
+            assertEq(reports[i].targetDebt, allocations[3 - i].debt);
+            // Lending more to a pair lowers its utilization.
+            assertEq(reports[i].projectedUtilization < reports[i].currentUtilization, true);
+        }
+        assertEq(newAPR > curAPR, true);
+        assertEq(isSuccess, true);
+        (uint256 newInterest, uint256 curInterest) = starter.getInterest(address(0));
+        assertEq(newInterest > curInterest, true);
+        assertEq(starter.getAppliedConstraints(address(0)).minDebts.length, 0);
+        assertEq(starter.getNetGain(address(0)), 0);
+        assertEq(starter.getUnallocatedAmount(address(0)), 0);
+        assertEq(starter.getErrorCode(address(0)), 0);
+        ZKOptimalAllocation.Snapshot memory snapshot = starter.getSnapshot(address(0));
+        assertEq(snapshot.vault, address(0));
+        assertEq(snapshot.inputHash, keccak256(input));
+        assertEq(snapshot.timestamp, block.timestamp);
+        assertEq(starter.pendingInputs(keccak256(input)), false);
+    }
+
+    // Test the ZKOptimalAllocation contract by mocking an on-chain callback request
+    function testOnChainMock() public withRelay {
+        // Deploy a new starter instance
+        ZKOptimalAllocation starter = new ZKOptimalAllocation(
+            IBonsaiRelay(bonsaiRelay),
+            queryImageId("OPTIMAL_ALLOCATION")
+        );
+
+        // Anticipate an on-chain callback request to the relay
+        vm.expectCall(address(bonsaiRelay), abi.encodeWithSelector(IBonsaiRelay.requestCallback.selector));
+        // Request the on-chain callback
+        IDebtManager.StrategyAllocation[] memory initialDatas;
+        starter.startOptimalAllocation(
+            IVault(address(0)),
+            100,
+            0,
+            initialDatas
+        );
+
+        // Anticipate a callback invocation on the starter contract
+        vm.expectCall(address(starter), abi.encodeWithSelector(ZKOptimalAllocation.onResult.selector));
+        // Relay the solution as a callback
+        runPendingCallbackRequest();
+
+        // Validate the optimal allocation value
+        (
+            IDebtManager.StrategyAllocation[] memory allocations, 
+            uint256 newAPR, 
+            uint256 curAPR, 
+            bool isSuccess
+        ) = starter.getResult(address(0));
+        assertEq(allocations.length, 0);
+        assertEq(starter.getActions(address(0)).length, 0);
+        assertEq(starter.getStrategyReports(address(0)).length, 0);
+        assertEq(newAPR, 0);
+        assertEq(curAPR, 0);
+        assertEq(isSuccess, false);
+        assertEq(starter.getErrorCode(address(0)), 0);
+    }
+
+    // Test a single callback allocating two vaults that share their strategies
+    function testOffChainMultiVaultMock() public withRelay {
+        bytes32 imageId = queryImageId("OPTIMAL_ALLOCATION");
+        ZKOptimalAllocation starter = new ZKOptimalAllocation(
+            IBonsaiRelay(bonsaiRelay),
+            imageId
+        );
+
+        address otherVault = address(0xBEEF);
+        ZKOptimalAllocation.VaultInput[] memory vaults = new ZKOptimalAllocation.VaultInput[](2);
+        vaults[0] = _offChainVaultInput(address(0), 14000 * 10 ** 18);
+        vaults[1] = _offChainVaultInput(otherVault, 7000 * 10 ** 18);
+        bytes memory input = abi.encode(vaults);
+        starter.expectResult(keccak256(input));
+        runCallbackRequest(
+            imageId,
+            input,
+            address(starter),
+            starter.onResult.selector,
+            6000000
+        );
+
+        // The first vault is allocated as if it were alone.
+        (IDebtManager.StrategyAllocation[] memory allocations, , , bool isSuccess) = starter.getResult(address(0));
+        assertEq(isSuccess, true);
+        assertEq(allocations[0].strategy, address(4));
+        assertEq(allocations[0].debt, uint256(3000000000000000000000));
+
+        // The second vault starts from the utilization the first one leaves on the shared pairs.
+        ZKOptimalAllocation.StrategyReport[] memory first = starter.getStrategyReports(address(0));
+        ZKOptimalAllocation.StrategyReport[] memory second = starter.getStrategyReports(otherVault);
+        assertEq(second.length, 4);
+        for (uint256 i; i < second.length; ++i) {
+            assertEq(second[i].strategy, first[i].strategy);
+            assertEq(second[i].currentUtilization, first[i].projectedUtilization);
+        }
+        assertEq(starter.getSnapshot(otherVault).vault, otherVault);
+        assertEq(starter.getSnapshot(otherVault).inputHash, keccak256(input));
+        assertEq(starter.pendingInputs(keccak256(input)), false);
+    }
+
+    // Test that multi-vault requests are bounded so that their callback gas limit fits in a block
+    function testMultiVaultRejectsTooManyVaults() public withRelay {
+        ZKOptimalAllocation starter = new ZKOptimalAllocation(
+            IBonsaiRelay(bonsaiRelay),
+            queryImageId("OPTIMAL_ALLOCATION")
+        );
+
+        ZKOptimalAllocation.VaultRequest[] memory requests =
+            new ZKOptimalAllocation.VaultRequest[](starter.MAX_VAULTS_PER_REQUEST() + 1);
+        vm.expectRevert(ZKOptimalAllocation.AG_TOO_MANY_VAULTS.selector);
+        starter.startMultiVaultAllocation(requests);
+        vm.expectRevert(ZKOptimalAllocation.AG_TOO_MANY_VAULTS.selector);
+        starter.multiVaultAllocationInput(requests);
+    }
+
+    // Test that strategies with a non-Sturdy rate model are read through their market accounting only
+    function testLinearKinkStrategyReadsMarketAccounting() public withRelay {
+        ZKOptimalAllocation starter = new ZKOptimalAllocation(
+            IBonsaiRelay(bonsaiRelay),
+            queryImageId("OPTIMAL_ALLOCATION")
+        );
+        address vault = address(0xa11);
+        address strategy = address(0xb22);
+        vm.etch(vault, hex"00");
+        vm.etch(strategy, hex"00");
+        vm.mockCall(
+            strategy,
+            abi.encodeCall(ILendingMarketStrategy.marketAccounting, ()),
+            abi.encode(1000e18, 800e18, 1e5, 3e9, 10000, block.timestamp - 60, false)
+        );
+        vm.mockCall(vault, abi.encodeCall(IVault.totalAssets, ()), abi.encode(500e18));
+        vm.mockCall(vault, abi.encodeCall(IVault.minimum_total_idle, ()), abi.encode(0));
+        vm.mockCall(
+            vault,
+            abi.encodeCall(IVault.strategies, (strategy)),
+            abi.encode(IVault.StrategyParams(1, 1, 0, 500e18))
+        );
+
+        ZKOptimalAllocation.RateModelKind linearKink = ZKOptimalAllocation.RateModelKind.LinearKink;
+        vm.expectRevert(ZKOptimalAllocation.AG_INVALID_CONFIGURATION.selector);
+        starter.setRateModel(strategy, ZKOptimalAllocation.RateModelParams(linearKink, 0, 1, 2, 1e5 + 1));
+        starter.setRateModel(strategy, ZKOptimalAllocation.RateModelParams(linearKink, 0, 1, 2, 8e4));
+
+        IDebtManager.StrategyAllocation[] memory initialDatas = new IDebtManager.StrategyAllocation[](1);
+        initialDatas[0].strategy = strategy;
+        ZKOptimalAllocation.VaultInput[] memory inputs = abi.decode(
+            starter.optimalAllocationInput(IVault(vault), 100, 0, initialDatas), (ZKOptimalAllocation.VaultInput[])
+        );
+        ZKOptimalAllocation.SturdyStrategyDataParams memory data = inputs[0].sturdyDatas[0];
+        assertEq(data.totalAsset, 1000e18);
+        assertEq(data.totalBorrow, 800e18);
+        assertEq(data.UTIL_PREC, 1e5);
+        assertEq(data.ratePerSec, 3e9);
+        assertEq(data.feeToProtocolRate, 10000);
+        assertEq(data.curTimestamp, block.timestamp);
+        assertEq(data.MAX_TARGET_UTIL, 0);
+        assertEq(uint8(inputs[0].rateModels[0].kind), uint8(linearKink));
+    }
+
+    // Guest input of a single vault with 14000 assets available.
+    function _offChainInput() internal view returns (bytes memory) {
+        ZKOptimalAllocation.VaultInput[] memory vaults = new ZKOptimalAllocation.VaultInput[](1);
+        vaults[0] = _offChainVaultInput(address(0), 14000 * 10 ** 18);
+        return abi.encode(vaults);
+    }
+
+    // Input of a vault allocating `totalAvailable` over four strategies, as read from mainnet with no
+    // protocol fee.
+    function _offChainVaultInput(
+        address vault,
+        uint256 totalAvailable
+    ) internal view returns (ZKOptimalAllocation.VaultInput memory input) {
         IDebtManager.StrategyAllocation[] memory initialDatas = new IDebtManager.StrategyAllocation[](4);
         for (uint256 i; i < 4; ++i) {
             initialDatas[i].strategy = address(uint160(i + 1));
@@ -96,7 +313,8 @@ contract ZKOptimalAllocationTest is BonsaiTest {
             172800,
             200000000000000000,
             1000000000000000000,
-            false
+            false,
+            0
         );
         sturdyDatas[1] = ZKOptimalAllocation.SturdyStrategyDataParams(
             1697739119,
@@ -115,7 +333,8 @@ contract ZKOptimalAllocationTest is BonsaiTest {
             172800,
             200000000000000000,
             1000000000000000000,
-            false
+            false,
+            0
         );
         sturdyDatas[2] = ZKOptimalAllocation.SturdyStrategyDataParams(
             1697739119,
@@ -134,7 +353,8 @@ contract ZKOptimalAllocationTest is BonsaiTest {
             172800,
             200000000000000000,
             1000000000000000000,
-            false
+            false,
+            0
         );
         sturdyDatas[3] = ZKOptimalAllocation.SturdyStrategyDataParams(
             1697739119,
@@ -153,78 +373,17 @@ contract ZKOptimalAllocationTest is BonsaiTest {
             172800,
             200000000000000000,
             1000000000000000000,
-            false
-        );
-
-        runCallbackRequest(
-            imageId,
-            abi.encode(
-                chunkCount,
-                totalInitialAmount,
-                totalAvailable,
-                initialDatas,
-                strategyDatas,
-                sturdyDatas
-            ), 
-            address(starter), 
-            starter.onResult.selector, 
-            BONSAI_CALLBACK_GAS_LIMIT
-        );
-
-        // Validate the optimal allocation value
-        (
-            IDebtManager.StrategyAllocation[] memory allocations, 
-            uint256 newAPR, 
-            uint256 curAPR, 
-            bool isSuccess
-        ) = starter.getResult();
-
-        assertEq(allocations[0].strategy, address(4));
-        assertEq(allocations[0].debt, uint256(2940000000000000000000));
-        assertEq(allocations[1].strategy, address(3));
-        assertEq(allocations[1].debt, uint256(2940000000000000000000));
-        assertEq(allocations[2].strategy, address(2));
-        assertEq(allocations[2].debt, uint256(4900000000000000000000));
-        assertEq(allocations[3].strategy, address(1));
-        assertEq(allocations[3].debt, uint256(3220000000000000000000));
-        assertEq(newAPR > curAPR, true);
-        assertEq(isSuccess, true);
-    }
-
-    // Test the ZKOptimalAllocation contract by mocking an on-chain callback request
-    function testOnChainMock() public withRelay {
-        // Deploy a new starter instance
-        ZKOptimalAllocation starter = new ZKOptimalAllocation(
-            IBonsaiRelay(bonsaiRelay),
-            queryImageId("OPTIMAL_ALLOCATION")
-        );
-
-        // Anticipate an on-chain callback request to the relay
-        vm.expectCall(address(bonsaiRelay), abi.encodeWithSelector(IBonsaiRelay.requestCallback.selector));
-        // Request the on-chain callback
-        IDebtManager.StrategyAllocation[] memory initialDatas;
-        starter.startOptimalAllocation(
-            IVault(address(0)),
-            100,
-            0,
-            initialDatas
+            false,
+            0
         );
 
-        // Anticipate a callback invocation on the starter contract
-        vm.expectCall(address(starter), abi.encodeWithSelector(ZKOptimalAllocation.onResult.selector));
-        // Relay the solution as a callback
-        runPendingCallbackRequest();
-
-        // Validate the optimal allocation value
-        (
-            IDebtManager.StrategyAllocation[] memory allocations, 
-            uint256 newAPR, 
-            uint256 curAPR, 
-            bool isSuccess
-        ) = starter.getResult();
-        assertEq(allocations.length, 0);
-        assertEq(newAPR, 0);
-        assertEq(curAPR, 0);
-        assertEq(isSuccess, false);
+        input.chunkCount = 100;
+        input.totalAvailable = totalAvailable;
+        input.initialDatas = initialDatas;
+        input.strategyDatas = strategyDatas;
+        input.sturdyDatas = sturdyDatas;
+        input.objective = uint8(ZKOptimalAllocation.Objective.TotalInterest);
+        input.constraints = ZKOptimalAllocation.AllocationConstraints(new uint256[](0), 0, 0, false);
+        input.snapshot = ZKOptimalAllocation.InputSnapshot(vault, block.timestamp);
     }
 }
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: ISC
+pragma solidity 0.8.21;
+
+/// @notice Rate model of a Sturdy pair, reproducing the math of the deployed `VariableInterestRate`
+///         contract so the guest's rate model can be checked against it.
+/// @dev Utilization is in UTIL_PREC (1e5) and rates are per second in 1e18 precision.
+contract VariableInterestRate {
+    uint256 public constant RATE_PREC = 1e18;
+    uint256 public constant UTIL_PREC = 1e5;
+
+    uint256 public immutable MIN_TARGET_UTIL;
+    uint256 public immutable MAX_TARGET_UTIL;
+    uint256 public immutable VERTEX_UTILIZATION;
+    uint64 public immutable MIN_FULL_UTIL_RATE;
+    uint64 public immutable MAX_FULL_UTIL_RATE;
+    uint64 public immutable ZERO_UTIL_RATE;
+    uint256 public immutable RATE_HALF_LIFE;
+    uint256 public immutable VERTEX_RATE_PERCENT;
+
+    constructor(
+        uint256 vertexUtilization,
+        uint256 vertexRatePercentOfDelta,
+        uint256 minUtil,
+        uint256 maxUtil,
+        uint64 zeroUtilizationRate,
+        uint64 minFullUtilizationRate,
+        uint64 maxFullUtilizationRate,
+        uint256 rateHalfLife
+    ) {
+        MIN_TARGET_UTIL = minUtil;
+        MAX_TARGET_UTIL = maxUtil;
+        VERTEX_UTILIZATION = vertexUtilization;
+        ZERO_UTIL_RATE = zeroUtilizationRate;
+        MIN_FULL_UTIL_RATE = minFullUtilizationRate;
+        MAX_FULL_UTIL_RATE = maxFullUtilizationRate;
+        RATE_HALF_LIFE = rateHalfLife;
+        VERTEX_RATE_PERCENT = vertexRatePercentOfDelta;
+    }
+
+    function getFullUtilizationInterest(
+        uint256 deltaTime,
+        uint256 utilization,
+        uint64 fullUtilizationInterest
+    ) public view returns (uint64 newFullUtilizationInterest) {
+        if (utilization < MIN_TARGET_UTIL) {
+            uint256 deltaUtilization = ((MIN_TARGET_UTIL - utilization) * 1e18) / MIN_TARGET_UTIL;
+            uint256 decayGrowth = (RATE_HALF_LIFE * 1e36) + (deltaUtilization * deltaUtilization * deltaTime);
+            newFullUtilizationInterest = uint64((fullUtilizationInterest * (RATE_HALF_LIFE * 1e36)) / decayGrowth);
+        } else if (utilization > MAX_TARGET_UTIL) {
+            uint256 deltaUtilization = ((utilization - MAX_TARGET_UTIL) * 1e18) / (UTIL_PREC - MAX_TARGET_UTIL);
+            uint256 decayGrowth = (RATE_HALF_LIFE * 1e36) + (deltaUtilization * deltaUtilization * deltaTime);
+            newFullUtilizationInterest = uint64((fullUtilizationInterest * decayGrowth) / (RATE_HALF_LIFE * 1e36));
+        } else {
+            newFullUtilizationInterest = fullUtilizationInterest;
+        }
+        if (newFullUtilizationInterest > MAX_FULL_UTIL_RATE) {
+            newFullUtilizationInterest = MAX_FULL_UTIL_RATE;
+        } else if (newFullUtilizationInterest < MIN_FULL_UTIL_RATE) {
+            newFullUtilizationInterest = MIN_FULL_UTIL_RATE;
+        }
+    }
+
+    function getNewRate(
+        uint256 deltaTime,
+        uint256 utilization,
+        uint64 oldFullUtilizationInterest
+    ) external view returns (uint64 newRatePerSec, uint64 newFullUtilizationInterest) {
+        newFullUtilizationInterest = getFullUtilizationInterest(deltaTime, utilization, oldFullUtilizationInterest);
+
+        uint256 vertexInterest =
+            (((newFullUtilizationInterest - ZERO_UTIL_RATE) * VERTEX_RATE_PERCENT) / RATE_PREC) + ZERO_UTIL_RATE;
+        if (utilization < VERTEX_UTILIZATION) {
+            newRatePerSec =
+                uint64(ZERO_UTIL_RATE + (utilization * (vertexInterest - ZERO_UTIL_RATE)) / VERTEX_UTILIZATION);
+        } else {
+            newRatePerSec = uint64(
+                vertexInterest
+                    + ((utilization - VERTEX_UTILIZATION) * (newFullUtilizationInterest - vertexInterest))
+                        / (UTIL_PREC - VERTEX_UTILIZATION)
+            );
+        }
+    }
+}